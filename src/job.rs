@@ -0,0 +1,901 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::utils::certificates::{
+    domain_cert_path, download_certificates, remove_stale_domains, rollback_certificates,
+};
+use crate::utils::certvalidation::{CertificateInfo, leaf_certificate};
+use crate::utils::configuration::{
+    ContainerAction, ContainerConfiguration, DomainConfiguration, JobConfiguration,
+    SourceConfiguration,
+};
+use crate::utils::docker::DockerClient;
+use crate::utils::errors::{ConfigurationError, MoorenewError};
+use crate::utils::expiry::{CertificateExpiry, Expiry, MAIN_CERTIFICATE};
+use crate::utils::history::{
+    self, CertificateChange, CertificateRecord, ContainerResult, HistoryEntry,
+};
+use crate::utils::npm::find_certificate_dir;
+use crate::utils::source::{
+    AcmeSource, CaddySource, CertificateSource, FileAccess, HttpsSource, LocalFiles, LocalSource,
+    SftpSource, TraefikSource,
+};
+use crate::utils::ssh::{HostKeyVerification, SSHClient, SshAuthentication};
+use crate::utils::sshconfig::resolve_route;
+use crate::utils::state::{self, InstalledCertificate, RunResult};
+use crate::utils::tlsverify::verify_served_certificates;
+
+/// Result of [`Job::plan`].
+#[derive(Debug, Clone)]
+pub enum Plan {
+    /// The installed certificates match the ones on the remote host.
+    UpToDate,
+    /// The remote certificates differ from the installed ones and passed validation.
+    Update {
+        /// New main certificate, `None` if only SNI domains change.
+        certificate: Option<CertificateInfo>,
+        /// SNI domains with a new certificate.
+        domains: Vec<String>,
+        /// SNI domains which are not configured anymore and would be removed.
+        removed_domains: Vec<String>,
+    },
+}
+
+/// Result of [`Job::apply`].
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum JobOutcome {
+    UpToDate,
+    Updated {
+        /// Installed main certificate, `None` if only SNI domains changed.
+        certificate: Option<CertificateInfo>,
+        /// SNI domains with a newly installed certificate.
+        domains: Vec<String>,
+        /// SNI domains which were not configured anymore and got removed.
+        removed_domains: Vec<String>,
+        /// Containers which could not pick up the new certificates.
+        failed_containers: Vec<String>,
+        /// Summary of the served certificate check, `None` if it is not configured.
+        tls_verification: Option<String>,
+    },
+}
+
+/// Result of [`Job::rollback`].
+#[derive(Debug, Clone)]
+pub struct RollbackOutcome {
+    /// Backup directories the certificates were restored from, the main pair first.
+    pub backups: Vec<PathBuf>,
+    /// Containers which could not be restarted.
+    pub failed_containers: Vec<String>,
+}
+
+/// A single certificate sync from one remote host into one mailcow installation.
+#[derive(Debug, Clone)]
+pub struct Job {
+    configuration: JobConfiguration,
+    state_dir: PathBuf,
+}
+
+impl Job {
+    /// Creates a job keeping its backups in `state_dir`, see [`Configuration::state_dir`].
+    ///
+    /// [`Configuration::state_dir`]: crate::utils::configuration::Configuration::state_dir
+    pub fn from_config(configuration: JobConfiguration, state_dir: PathBuf) -> Job {
+        Job {
+            configuration,
+            state_dir,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.configuration.name
+    }
+
+    pub fn configuration(&self) -> &JobConfiguration {
+        &self.configuration
+    }
+
+    /// plan checks if the remote certificates differ from the installed ones and validates them,
+    /// without writing anything or touching the containers.
+    #[instrument(fields(job = %self.configuration.name, result), skip(self))]
+    pub fn plan(&self) -> Result<Plan, MoorenewError> {
+        let mut changes = Changes::default();
+        let plan = self.download(&mut changes, true).map(|()| {
+            if changes.is_empty() {
+                Plan::UpToDate
+            } else {
+                Plan::Update {
+                    certificate: changes.certificate,
+                    domains: changes.domains,
+                    removed_domains: changes.removed_domains,
+                }
+            }
+        });
+
+        let result = match &plan {
+            Ok(Plan::UpToDate) => "up to date",
+            Ok(Plan::Update { .. }) => "update available",
+            Err(e) => {
+                error!(error = %e, "job failed");
+                "failed"
+            }
+        };
+        tracing::Span::current().record("result", result);
+
+        plan
+    }
+
+    /// apply installs the remote certificates if they differ from the installed ones, applies the
+    /// configured action to every container and verifies the served certificate if configured.
+    #[instrument(fields(job = %self.configuration.name, result, tls_verification), skip(self))]
+    pub fn apply(&self) -> Result<JobOutcome, MoorenewError> {
+        let mut entry = HistoryEntry::new(&self.configuration.name);
+        let outcome = self.sync(&mut entry);
+
+        let result = match &outcome {
+            Ok(JobOutcome::UpToDate) => "up to date",
+            Ok(JobOutcome::Updated {
+                failed_containers, ..
+            }) if failed_containers.is_empty() => "success",
+            Ok(JobOutcome::Updated { .. }) => "partially successful",
+            Err(e) => {
+                error!(error = %e, "job failed");
+                "failed"
+            }
+        };
+        tracing::Span::current().record("result", result);
+        self.record_run(&outcome);
+
+        // runs which changed nothing are only part of the state, not of the history
+        if !entry.certificates.is_empty() || outcome.is_err() {
+            entry.result = result.to_string();
+            entry.error = outcome.as_ref().err().map(|e| e.to_string());
+            record_history(&self.state_dir, &entry);
+        }
+
+        outcome
+    }
+
+    /// Records the outcome and the installed main certificate in the state file shown by
+    /// `moorenew status`.
+    fn record_run(&self, outcome: &Result<JobOutcome, MoorenewError>) {
+        let result = match outcome {
+            Ok(JobOutcome::UpToDate) => RunResult::UpToDate,
+            Ok(JobOutcome::Updated {
+                failed_containers, ..
+            }) if failed_containers.is_empty() => RunResult::Updated,
+            Ok(JobOutcome::Updated { .. }) => RunResult::PartiallyUpdated,
+            Err(e) => RunResult::Failed(e),
+        };
+        let certificate = installed_certificate(Path::new(&self.configuration.mail_cert_path))
+            .map(InstalledCertificate::from);
+
+        state::record_run(
+            &self.state_dir,
+            &self.configuration.name,
+            result,
+            certificate,
+        );
+    }
+
+    /// rollback restores the last backed up certificates of the main pair and of every SNI domain
+    /// which has a backup and restarts the containers.
+    #[instrument(fields(job = %self.configuration.name), skip(self))]
+    pub fn rollback(&self) -> Result<RollbackOutcome, MoorenewError> {
+        let job = &self.configuration;
+        let mail_cert_path = Path::new(&job.mail_cert_path);
+        let mut backups = Vec::new();
+        let mut entry = HistoryEntry::new(&job.name);
+
+        let mut certificates = vec![(
+            MAIN_CERTIFICATE.to_string(),
+            mail_cert_path.to_path_buf(),
+            job.backup_path(&self.state_dir),
+        )];
+        for domain in &job.domains {
+            certificates.push((
+                domain.domain.clone(),
+                domain_cert_path(mail_cert_path, &domain.domain, false)?,
+                job.domain_backup_path(&self.state_dir, &domain.domain),
+            ));
+        }
+        for (certificate, cert_path, backup_path) in certificates {
+            let previous = installed_certificate(&cert_path);
+            match rollback_certificates(&cert_path, &backup_path) {
+                Ok(backup) => {
+                    if let Some(restored) = installed_certificate(&cert_path) {
+                        entry.certificates.push(CertificateChange {
+                            certificate,
+                            source_host: None,
+                            source: backup.display().to_string(),
+                            previous: previous.as_ref().map(CertificateRecord::from),
+                            new: CertificateRecord::from(&restored),
+                        });
+                    }
+                    backups.push(backup);
+                }
+                Err(MoorenewError::NoBackupAvailable) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if backups.is_empty() {
+            return Err(MoorenewError::NoBackupAvailable);
+        }
+
+        entry.containers = restart_containers(
+            &self.configuration.containers,
+            self.configuration.compose_project.as_deref(),
+        );
+        let failed_containers = failed_containers(&entry.containers);
+        entry.result = "rolled back".to_string();
+        record_history(&self.state_dir, &entry);
+        // the replaced certificates are not installed again by the next runs
+        let replaced: Vec<String> = entry
+            .certificates
+            .iter()
+            .filter_map(|change| change.previous.as_ref())
+            .map(|previous| previous.fingerprint.clone())
+            .collect();
+        state::record_run(
+            &self.state_dir,
+            &job.name,
+            RunResult::RolledBack(&replaced),
+            installed_certificate(mail_cert_path).map(InstalledCertificate::from),
+        );
+
+        Ok(RollbackOutcome {
+            backups,
+            failed_containers,
+        })
+    }
+
+    /// check_expiry reads the expiry of the installed certificates and of the certificates at their
+    /// sources. Sources which cannot be read are logged and reported without an expiry, so the
+    /// installed certificates are checked even if the source is unreachable.
+    #[instrument(fields(job = %self.configuration.name), skip(self))]
+    pub fn check_expiry(&self) -> Vec<CertificateExpiry> {
+        let job = &self.configuration;
+        let mail_cert_path = Path::new(&job.mail_cert_path);
+        let client = if job.uses_ssh() {
+            self.connect()
+                .inspect_err(
+                    |e| warn!(error = %e, "could not connect to read the source certificates"),
+                )
+                .ok()
+        } else {
+            None
+        };
+
+        let mut expiries = vec![self.certificate_expiry(
+            MAIN_CERTIFICATE,
+            &job.source,
+            client.as_ref(),
+            Target {
+                cert_path: mail_cert_path,
+                hostnames: &job.hostnames,
+                npm_cert_path: &job.npm_cert_path,
+                npm_domain: job.npm_domain.as_deref(),
+            },
+        )];
+        for domain in &job.domains {
+            let hostnames = domain.hostnames();
+            expiries.push(
+                self.certificate_expiry(
+                    &domain.domain,
+                    domain.source.as_ref().unwrap_or(&SourceConfiguration::Sftp),
+                    client.as_ref(),
+                    Target {
+                        cert_path: &mail_cert_path.join(&domain.domain),
+                        hostnames: &hostnames,
+                        npm_cert_path: &domain.npm_cert_path,
+                        npm_domain: domain
+                            .npm_cert_path
+                            .is_empty()
+                            .then(|| hostnames[0].as_str()),
+                    },
+                ),
+            );
+        }
+
+        if let Some(client) = client {
+            client.disconnect();
+        }
+        expiries
+    }
+
+    fn certificate_expiry(
+        &self,
+        certificate: &str,
+        source: &SourceConfiguration,
+        client: Option<&SSHClient>,
+        target: Target,
+    ) -> CertificateExpiry {
+        let installed = std::fs::read(target.cert_path.join("cert.pem"))
+            .map_err(|e| MoorenewError::CertificateSource {
+                location: target.cert_path.display().to_string(),
+                error: e,
+            })
+            .and_then(|fullchain| Expiry::from_fullchain(&fullchain))
+            .inspect_err(|e| debug!(error = %e, certificate, "no installed certificate"))
+            .ok();
+
+        // ACME sources have no certificate of their own, they renew the installed one
+        let source = if matches!(source, SourceConfiguration::Acme(_))
+            || (source.uses_ssh() && client.is_none())
+        {
+            None
+        } else {
+            self.open_source(source, client, target, true)
+                .and_then(|source| source.fetch())
+                .and_then(|pair| Expiry::from_fullchain(&pair.fullchain))
+                .inspect_err(
+                    |e| warn!(error = %e, certificate, "could not read the source certificate"),
+                )
+                .ok()
+        };
+
+        info!(
+            certificate,
+            installed_days = installed.as_ref().map(|expiry| expiry.days),
+            source_days = source.as_ref().map(|expiry| expiry.days),
+            "certificate expiry"
+        );
+        CertificateExpiry {
+            certificate: certificate.to_string(),
+            installed,
+            source,
+        }
+    }
+
+    fn connect(&self) -> Result<SSHClient, MoorenewError> {
+        let job = &self.configuration;
+        let known_hosts_path = job.known_hosts_path()?;
+        let host_key_verification = HostKeyVerification {
+            policy: &job.host_key_policy,
+            fingerprint: job.host_key_fingerprint.as_deref(),
+            known_hosts_path: &known_hosts_path,
+        };
+
+        SSHClient::connect(
+            &resolve_route(job)?,
+            &SshAuthentication {
+                methods: &job.auth_methods,
+                private_key: job.private_key.as_deref(),
+                private_key_path: &job.private_key_path,
+                public_key_path: &job.public_key_path,
+                identity_files: &[],
+                passphrase: job.private_key_passphrase.as_deref(),
+            },
+            &host_key_verification,
+            &job.connection,
+        )
+    }
+
+    /// Downloads the certificates into `changes`, retrying connection and transfer errors with an
+    /// exponential backoff. Certificates installed before an attempt failed stay part of the
+    /// changes even if the download fails in the end, as the next attempt finds them up to date.
+    fn download(&self, changes: &mut Changes, dry_run: bool) -> Result<(), MoorenewError> {
+        let connection = &self.configuration.connection;
+        let mut retry = 0;
+
+        loop {
+            match self.download_attempt(changes, dry_run) {
+                Err(e) if e.is_transient() && retry < connection.retries => {
+                    retry += 1;
+                    let delay = connection.retry_delay(retry);
+                    warn!(error = %e, retry, retries = connection.retries, delay_seconds = delay.as_secs(), "download failed, retrying");
+                    std::thread::sleep(delay);
+                }
+                Err(e) => {
+                    if retry > 0 {
+                        error!(error = %e, retries = retry, "download failed, giving up");
+                    }
+                    return Err(e);
+                }
+                Ok(()) => return Ok(()),
+            }
+        }
+    }
+
+    fn download_attempt(&self, changes: &mut Changes, dry_run: bool) -> Result<(), MoorenewError> {
+        if !self.configuration.uses_ssh() {
+            return self.download_all(None, changes, dry_run);
+        }
+
+        let client = self.connect()?;
+        let download_result = self.download_all(Some(&client), changes, dry_run);
+        client.disconnect();
+
+        download_result
+    }
+
+    /// Downloads the main pair and the pair of every SNI domain into `changes` and removes the
+    /// directories of domains which are not configured anymore. Certificates which were rolled
+    /// back are skipped.
+    fn download_all(
+        &self,
+        client: Option<&SSHClient>,
+        changes: &mut Changes,
+        dry_run: bool,
+    ) -> Result<(), MoorenewError> {
+        let job = &self.configuration;
+        let mail_cert_path = Path::new(&job.mail_cert_path);
+        let rolled_back = state::rolled_back(&self.state_dir, &job.name);
+
+        let source = self.open_source(
+            &job.source,
+            client,
+            Target {
+                cert_path: mail_cert_path,
+                hostnames: &job.hostnames,
+                npm_cert_path: &job.npm_cert_path,
+                npm_domain: job.npm_domain.as_deref(),
+            },
+            dry_run,
+        )?;
+        let previous = installed_certificate(mail_cert_path);
+        let certificate = optional_change(download_certificates(
+            source.as_ref(),
+            mail_cert_path,
+            &job.hostnames,
+            &job.backup_path(&self.state_dir),
+            &rolled_back,
+            dry_run,
+        ))?;
+        if let Some(certificate) = certificate {
+            changes.replace(CertificateChange {
+                certificate: MAIN_CERTIFICATE.to_string(),
+                source_host: self.source_host(&job.source),
+                source: source.location(),
+                previous: previous.as_ref().map(CertificateRecord::from),
+                new: CertificateRecord::from(&certificate),
+            });
+            changes.certificate = Some(certificate);
+        }
+
+        for domain in &job.domains {
+            if let Some(change) = self.download_domain(client, domain, &rolled_back, dry_run)? {
+                if !changes.domains.contains(&domain.domain) {
+                    changes.domains.push(domain.domain.clone());
+                }
+                changes.replace(change);
+            }
+        }
+
+        let configured_domains: Vec<&str> = job
+            .domains
+            .iter()
+            .map(|domain| domain.domain.as_str())
+            .collect();
+        changes.removed_domains =
+            remove_stale_domains(mail_cert_path, &configured_domains, dry_run)?;
+
+        Ok(())
+    }
+
+    #[instrument(fields(domain = %domain.domain), skip(self, client, domain, rolled_back))]
+    fn download_domain(
+        &self,
+        client: Option<&SSHClient>,
+        domain: &DomainConfiguration,
+        rolled_back: &[String],
+        dry_run: bool,
+    ) -> Result<Option<CertificateChange>, MoorenewError> {
+        let job = &self.configuration;
+        let domain_path =
+            domain_cert_path(Path::new(&job.mail_cert_path), &domain.domain, dry_run)?;
+        let hostnames = domain.hostnames();
+        let source_configuration = domain.source.as_ref().unwrap_or(&SourceConfiguration::Sftp);
+        let source = self.open_source(
+            source_configuration,
+            client,
+            Target {
+                cert_path: &domain_path,
+                hostnames: &hostnames,
+                npm_cert_path: &domain.npm_cert_path,
+                npm_domain: domain
+                    .npm_cert_path
+                    .is_empty()
+                    .then(|| hostnames[0].as_str()),
+            },
+            dry_run,
+        )?;
+
+        let previous = installed_certificate(&domain_path);
+        let certificate = optional_change(download_certificates(
+            source.as_ref(),
+            &domain_path,
+            &hostnames,
+            &job.domain_backup_path(&self.state_dir, &domain.domain),
+            rolled_back,
+            dry_run,
+        ))?;
+
+        Ok(certificate.map(|certificate| CertificateChange {
+            certificate: domain.domain.clone(),
+            source_host: self.source_host(source_configuration),
+            source: source.location(),
+            previous: previous.as_ref().map(CertificateRecord::from),
+            new: CertificateRecord::from(&certificate),
+        }))
+    }
+
+    /// Returns the SSH host `source` is read from, `None` if it does not use SSH.
+    fn source_host(&self, source: &SourceConfiguration) -> Option<String> {
+        source
+            .uses_ssh()
+            .then(|| self.configuration.sftp_host.clone())
+    }
+
+    /// Creates a certificate source for `target`. `client` has to be connected if the source uses
+    /// SSH.
+    fn open_source<'a>(
+        &'a self,
+        source: &'a SourceConfiguration,
+        client: Option<&'a SSHClient>,
+        target: Target<'a>,
+        dry_run: bool,
+    ) -> Result<Box<dyn CertificateSource + 'a>, MoorenewError> {
+        let job = &self.configuration;
+        let ssh_client =
+            || client.ok_or_else(|| MoorenewError::SshNotConnected(job.sftp_host.clone()));
+        let files = |remote: bool| -> Result<&'a dyn FileAccess, MoorenewError> {
+            Ok(if remote { ssh_client()? } else { &LocalFiles })
+        };
+
+        Ok(match source {
+            SourceConfiguration::Sftp => {
+                let client = ssh_client()?;
+                Box::new(SftpSource::new(
+                    client,
+                    resolve_npm_cert_path(client, job, target.npm_cert_path, target.npm_domain)?,
+                ))
+            }
+            SourceConfiguration::Local { path } => Box::new(LocalSource::new(PathBuf::from(path))),
+            SourceConfiguration::Https {
+                url,
+                token,
+                ca_file,
+            } => Box::new(HttpsSource::new(
+                url,
+                token.clone(),
+                ca_file.clone(),
+                Duration::from_secs(job.connection.connect_timeout),
+            )?),
+            SourceConfiguration::Traefik {
+                path,
+                domain,
+                resolver,
+                remote,
+            } => Box::new(TraefikSource::new(
+                files(*remote)?,
+                PathBuf::from(path),
+                domain.clone(),
+                resolver.clone(),
+            )),
+            SourceConfiguration::Caddy {
+                path,
+                domain,
+                issuer,
+                remote,
+            } => Box::new(CaddySource::new(
+                files(*remote)?,
+                PathBuf::from(path),
+                domain.clone(),
+                issuer.clone(),
+            )),
+            SourceConfiguration::Acme(acme) => Box::new(AcmeSource::new(
+                acme,
+                target.hostnames,
+                target.cert_path,
+                &self.state_dir,
+                Duration::from_secs(job.connection.connect_timeout),
+                dry_run,
+            )),
+        })
+    }
+
+    /// Installs the certificates and restarts the containers, recording both in `entry`. The
+    /// containers are restarted as soon as any certificate got installed, also if another one
+    /// failed, as the next run finds the installed ones up to date and would not restart them.
+    fn sync(&self, entry: &mut HistoryEntry) -> Result<JobOutcome, MoorenewError> {
+        let mut changes = Changes::default();
+        let download_result = self.download(&mut changes, false);
+        entry.certificates = std::mem::take(&mut changes.replaced);
+        if changes.is_empty() {
+            download_result?;
+            return Ok(JobOutcome::UpToDate);
+        }
+
+        entry.containers = restart_containers(
+            &self.configuration.containers,
+            self.configuration.compose_project.as_deref(),
+        );
+        download_result?;
+        let failed_containers = failed_containers(&entry.containers);
+
+        // the endpoints serve the main certificate, so there is nothing to compare if only SNI
+        // domains changed
+        let tls_verification = match (&self.configuration.tls_verification, &changes.certificate) {
+            (Some(verification), Some(certificate)) => {
+                match verify_served_certificates(
+                    &verification.host,
+                    &verification.ports,
+                    &certificate.fingerprint,
+                    Duration::from_secs(verification.retry_window),
+                ) {
+                    Ok(results) => {
+                        let ports: Vec<String> = results
+                            .iter()
+                            .map(|result| result.port.to_string())
+                            .collect();
+                        let summary =
+                            format!("served certificate verified on ports {}", ports.join(", "));
+                        tracing::Span::current().record("tls_verification", summary.as_str());
+                        Some(summary)
+                    }
+                    Err(e) => {
+                        tracing::Span::current().record("tls_verification", "failed");
+                        return Err(e);
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        Ok(JobOutcome::Updated {
+            certificate: changes.certificate,
+            domains: changes.domains,
+            removed_domains: changes.removed_domains,
+            failed_containers,
+            tls_verification,
+        })
+    }
+}
+
+/// Certificates changed by a download.
+#[derive(Default)]
+struct Changes {
+    certificate: Option<CertificateInfo>,
+    domains: Vec<String>,
+    removed_domains: Vec<String>,
+    /// The main certificate and the SNI certificates which got replaced, for the history.
+    replaced: Vec<CertificateChange>,
+}
+
+impl Changes {
+    fn is_empty(&self) -> bool {
+        self.certificate.is_none() && self.domains.is_empty() && self.removed_domains.is_empty()
+    }
+
+    /// Records `change`, replacing the change of the same certificate by an earlier attempt.
+    fn replace(&mut self, change: CertificateChange) {
+        self.replaced
+            .retain(|replaced| replaced.certificate != change.certificate);
+        self.replaced.push(change);
+    }
+}
+
+/// The installed certificate a source is opened for.
+struct Target<'a> {
+    /// Directory of the installed `cert.pem` and `key.pem`.
+    cert_path: &'a Path,
+    hostnames: &'a [String],
+    /// Locate the certificate of the SFTP source, see [`resolve_npm_cert_path`].
+    npm_cert_path: &'a str,
+    npm_domain: Option<&'a str>,
+}
+
+/// Returns `npm_cert_path`, or the certificate directory of `npm_domain` in `npm_live_path` if
+/// a domain is given.
+fn resolve_npm_cert_path(
+    client: &SSHClient,
+    job: &JobConfiguration,
+    npm_cert_path: &str,
+    npm_domain: Option<&str>,
+) -> Result<PathBuf, MoorenewError> {
+    match npm_domain {
+        Some(npm_domain) => find_certificate_dir(client, Path::new(&job.npm_live_path), npm_domain),
+        None => Ok(PathBuf::from(npm_cert_path)),
+    }
+}
+
+/// Turns [`MoorenewError::NoChanges`] into `None`.
+fn optional_change(
+    result: Result<CertificateInfo, MoorenewError>,
+) -> Result<Option<CertificateInfo>, MoorenewError> {
+    match result {
+        Ok(certificate) => Ok(Some(certificate)),
+        Err(MoorenewError::NoChanges) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Applies the configured action to every container and returns what happened to each of them.
+/// Compose services are looked up in `compose_project`.
+pub fn restart_containers(
+    containers: &[ContainerConfiguration],
+    compose_project: Option<&str>,
+) -> Vec<ContainerResult> {
+    let docker = DockerClient::from_env().with_compose_project(compose_project);
+
+    containers
+        .iter()
+        .map(
+            |container| match apply_container_action(&docker, container) {
+                Ok(result) => ContainerResult {
+                    name: container.name.clone(),
+                    result: result.to_string(),
+                    error: None,
+                },
+                Err(e) => {
+                    error!(error = %e, "failed to restart {}", container.name);
+                    ContainerResult {
+                        name: container.name.clone(),
+                        result: "failed".to_string(),
+                        error: Some(e.to_string()),
+                    }
+                }
+            },
+        )
+        .collect()
+}
+
+/// Returns the names of the containers which could not pick up the new certificates.
+fn failed_containers(results: &[ContainerResult]) -> Vec<String> {
+    results
+        .iter()
+        .filter(|result| result.failed())
+        .map(|result| result.name.clone())
+        .collect()
+}
+
+/// Reads the certificate installed in `cert_path`, `None` if there is none or it is unreadable.
+fn installed_certificate(cert_path: &Path) -> Option<CertificateInfo> {
+    std::fs::read(cert_path.join("cert.pem"))
+        .ok()
+        .and_then(|fullchain| leaf_certificate(&fullchain).ok())
+}
+
+/// Appends `entry` to the history and emits it as audit events. Failures are only logged, as the
+/// history must never fail a run.
+fn record_history(state_dir: &Path, entry: &HistoryEntry) {
+    entry.emit_audit_events();
+    if let Err(e) = history::append(state_dir, entry) {
+        warn!(error = %e, "could not append the run to the history");
+    }
+}
+
+/// Reloads the container in place if configured and falls back to a restart if the reload fails.
+/// Returns `reloaded` or `restarted`.
+fn apply_container_action(
+    docker: &DockerClient,
+    container: &ContainerConfiguration,
+) -> Result<&'static str, MoorenewError> {
+    let reload_result = match container.action {
+        ContainerAction::Restart => None,
+        ContainerAction::Exec => Some(match container.reload_command() {
+            Some(command) => docker.exec(&container.name, &command),
+            None => Err(MoorenewError::ConfigurationError(
+                ConfigurationError::ReloadCommandMissing(container.name.clone()),
+            )),
+        }),
+        ContainerAction::Signal => Some(docker.signal(&container.name, container.signal())),
+    };
+
+    match reload_result {
+        Some(Ok(_)) => {
+            info!("successfully reloaded {}", container.name);
+            return Ok("reloaded");
+        }
+        Some(Err(e)) => {
+            warn!(error = %e, "failed to reload {}, restarting it instead", container.name);
+        }
+        None => {}
+    }
+
+    docker.restart_container(&container.name)?;
+    info!("successfully restarted {}", container.name);
+    Ok("restarted")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Job, JobOutcome, MAIN_CERTIFICATE};
+    use crate::utils::configuration::parse_config;
+    use crate::utils::history::HistoryEntry;
+    use crate::utils::state::{self, State};
+    use crate::utils::testutil::{TempDir, self_signed_pair};
+    use std::fs;
+    use std::path::Path;
+
+    /// A job installing the pair in `<root>/source` into `<root>/ssl`, followed by `domains`.
+    fn local_job(root: &Path, domains: &str) -> Job {
+        fs::create_dir_all(root.join("source")).unwrap();
+        fs::create_dir_all(root.join("ssl")).unwrap();
+        let configuration = parse_config(&format!(
+            r#"
+buzz_urls = []
+
+[logging]
+level = "info"
+
+[[jobs]]
+source = {{ type = "local", path = "{source}" }}
+mail_cert_path = "{ssl}"
+hostnames = ["mail.example.com"]
+containers = ["moorenew-test-postfix"]
+{domains}"#,
+            source = root.join("source").display(),
+            ssl = root.join("ssl").display(),
+        ))
+        .unwrap();
+
+        Job::from_config(configuration.jobs[0].clone(), root.join("state"))
+    }
+
+    /// Writes a new pair for `mail.example.com` to `<root>/source`.
+    fn renew(root: &Path) {
+        let (fullchain, private_key) = self_signed_pair("mail.example.com", 1, 90);
+        fs::write(root.join("source/fullchain.pem"), fullchain).unwrap();
+        fs::write(root.join("source/privkey.pem"), private_key).unwrap();
+    }
+
+    #[test]
+    fn test_sync_restarts_containers_if_a_domain_fails() {
+        let root = TempDir::new("job-sync");
+        let job = local_job(
+            &root,
+            &format!(
+                r#"
+[[jobs.domains]]
+domain = "example.org"
+source = {{ type = "local", path = "{}" }}
+"#,
+                root.join("missing").display()
+            ),
+        );
+        renew(&root);
+
+        let mut entry = HistoryEntry::new(job.name());
+        assert!(job.sync(&mut entry).is_err());
+        assert!(root.join("ssl/cert.pem").exists());
+        assert_eq!(entry.certificates.len(), 1);
+        assert_eq!(entry.certificates[0].certificate, MAIN_CERTIFICATE);
+        // there is no such container, but the restart has to be attempted
+        assert_eq!(entry.containers.len(), 1);
+        assert_eq!(entry.containers[0].name, "moorenew-test-postfix");
+    }
+
+    #[test]
+    fn test_rolled_back_certificate_is_not_installed_again() {
+        let root = TempDir::new("job-rollback");
+        let job = local_job(&root, "");
+        let cert_path = root.join("ssl/cert.pem");
+
+        renew(&root);
+        job.apply().unwrap();
+        let first = fs::read(&cert_path).unwrap();
+        renew(&root);
+        job.apply().unwrap();
+        let second = fs::read(&cert_path).unwrap();
+
+        job.rollback().unwrap();
+        assert_eq!(fs::read(&cert_path).unwrap(), first);
+        assert!(matches!(job.apply(), Ok(JobOutcome::UpToDate)));
+        assert_eq!(fs::read(&cert_path).unwrap(), first);
+
+        let mut state = State::load(&root.join("state")).unwrap();
+        assert_eq!(state.clear_rolled_back(job.name()).len(), 1);
+        state.save(&root.join("state")).unwrap();
+        assert!(state::rolled_back(&root.join("state"), job.name()).is_empty());
+        assert!(matches!(job.apply(), Ok(JobOutcome::Updated { .. })));
+        assert_eq!(fs::read(&cert_path).unwrap(), second);
+
+        // a renewed certificate is installed right away
+        job.rollback().unwrap();
+        renew(&root);
+        assert!(matches!(job.apply(), Ok(JobOutcome::Updated { .. })));
+    }
+}
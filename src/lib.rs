@@ -0,0 +1,130 @@
+//! moorenew keeps the certificates of a mailcow installation in sync with a remote host, e.g. an
+//! Nginx Proxy Manager instance. The `moorenew` binary is a thin CLI on top of this crate.
+//!
+//! ```no_run
+//! use moorenew::Moorenew;
+//! use moorenew::utils::configuration::{config_path, read_config_from_file};
+//!
+//! # fn main() -> Result<(), moorenew::utils::errors::MoorenewError> {
+//! let moorenew = Moorenew::from_config(read_config_from_file(&config_path(None))?)?;
+//! for job in moorenew.jobs() {
+//!     if let moorenew::Plan::Update { domains, .. } = job.plan()? {
+//!         println!("{}: installing, SNI domains {:?}", job.name(), domains);
+//!         job.apply()?;
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod job;
+pub mod system;
+pub mod utils;
+
+pub use job::{Job, JobOutcome, Plan, RollbackOutcome};
+
+use std::path::{Path, PathBuf};
+
+use crate::utils::configuration::Configuration;
+use crate::utils::errors::{ConfigurationError, MoorenewError};
+use crate::utils::mailcow;
+
+/// Entry point of the library, holding the configuration all jobs are created from.
+#[derive(Debug, Clone)]
+pub struct Moorenew {
+    configuration: Configuration,
+    state_dir: PathBuf,
+}
+
+impl Moorenew {
+    /// Creates the entry point and fills the job fields which are not configured from the
+    /// mailcow installations, see [`mailcow::discover`].
+    pub fn from_config(mut configuration: Configuration) -> Result<Moorenew, MoorenewError> {
+        let state_dir = configuration.state_dir()?;
+        for job in &mut configuration.jobs {
+            mailcow::discover(job)?;
+        }
+
+        Ok(Moorenew {
+            configuration,
+            state_dir,
+        })
+    }
+
+    pub fn configuration(&self) -> &Configuration {
+        &self.configuration
+    }
+
+    /// Directory for logs and certificate backups.
+    pub fn state_dir(&self) -> &Path {
+        &self.state_dir
+    }
+
+    pub fn jobs(&self) -> Vec<Job> {
+        self.configuration
+            .jobs
+            .iter()
+            .map(|job| Job::from_config(job.clone(), self.state_dir.clone()))
+            .collect()
+    }
+
+    /// Returns the job called `name`. If no name is given, the only configured job is returned.
+    pub fn job(&self, name: Option<&str>) -> Result<Job, MoorenewError> {
+        let job = match name {
+            Some(name) => self
+                .configuration
+                .jobs
+                .iter()
+                .find(|job| job.name == name)
+                .ok_or_else(|| MoorenewError::UnknownJob(name.to_owned()))?,
+            None => match self.configuration.jobs.as_slice() {
+                [job] => job,
+                [] => {
+                    return Err(MoorenewError::ConfigurationError(
+                        ConfigurationError::NoJobsConfigured,
+                    ));
+                }
+                _ => return Err(MoorenewError::JobSelectionRequired),
+            },
+        };
+
+        Ok(Job::from_config(job.clone(), self.state_dir.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Moorenew;
+    use crate::utils::configuration::Configuration;
+    use crate::utils::errors::MoorenewError;
+
+    #[test]
+    fn test_job_selection() {
+        let mut configuration = Configuration::new();
+        configuration.state_dir = Some("/var/lib/moorenew".to_string());
+        assert_eq!(
+            Moorenew::from_config(configuration.clone())
+                .unwrap()
+                .job(None)
+                .unwrap()
+                .name(),
+            "default"
+        );
+
+        let mut second_job = configuration.jobs[0].clone();
+        second_job.name = "second".to_string();
+        configuration.jobs.push(second_job);
+        let moorenew = Moorenew::from_config(configuration).unwrap();
+
+        assert_eq!(moorenew.jobs().len(), 2);
+        assert_eq!(moorenew.job(Some("second")).unwrap().name(), "second");
+        assert!(matches!(
+            moorenew.job(Some("third")),
+            Err(MoorenewError::UnknownJob(_))
+        ));
+        assert!(matches!(
+            moorenew.job(None),
+            Err(MoorenewError::JobSelectionRequired)
+        ));
+    }
+}
@@ -1,34 +1,79 @@
-mod system;
-mod utils;
-
-use crate::system::serviceproviders::ServiceProvider;
-use crate::utils::certificates::download_certificates;
-use crate::utils::configuration::{Configuration, read_config_from_file};
-use crate::utils::errors::{ConfigurationError, MoorenewError};
-use crate::utils::logging;
-use crate::utils::ssh::SSHClient;
-use crate::utils::sshkeygen;
 use buzzrs::buzz;
-use clap::{Command, arg};
-use std::env;
-use std::path::Path;
-use std::process::{Output, exit};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Command, arg, value_parser};
+use moorenew::system::serviceproviders::ServiceProvider;
+use moorenew::system::{service, sysinfo};
+use moorenew::utils::configuration::{config_path, read_config_from_file, validate_config};
+use moorenew::utils::errors::{
+    ConfigurationError, EXIT_CONTAINERS_FAILED, EXIT_EXPIRING, EXIT_SUCCESS, EXIT_UP_TO_DATE,
+    MoorenewError,
+};
+use moorenew::utils::expiry::{ExpiryAlerts, expiry_warnings};
+use moorenew::utils::history::{self, HistoryFilter, format_history};
+use moorenew::utils::init::{self, InitOptions, Prompt};
+use moorenew::utils::state::{JobStatus, State, format_status_table};
+use moorenew::utils::{logging, sshkeygen};
+use moorenew::{JobOutcome, Moorenew, Plan};
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::time::Duration;
-use system::sysinfo;
 use tokio::time::sleep;
 use tracing::metadata::LevelFilter;
-use tracing::{error, info, instrument};
+use tracing::{error, info, instrument, warn};
 
 #[tokio::main]
-async fn main() -> Result<(), MoorenewError> {
-    let user_path = env::home_dir()
-        .ok_or_else(|| MoorenewError::ConfigurationError(ConfigurationError::HomeDirUnavailable))?;
-    let config_path = user_path.join(".moorenew/config.toml");
-    if !Path::new(config_path.as_os_str()).exists() {
-        Configuration::new().write_to_file()?
+async fn main() -> ExitCode {
+    let exit_code = match run_cli().await {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            let message = error_chain(&e);
+            if tracing::dispatcher::has_been_set() {
+                error!(error = %message, exit_code = e.exit_code(), "moorenew failed");
+            } else {
+                eprintln!("error: {message}");
+            }
+            e.exit_code()
+        }
+    };
+
+    sleep(Duration::from_millis(10)).await;
+
+    ExitCode::from(exit_code)
+}
+
+/// Joins the messages of `error` and its sources, e.g. `configuration error: could not parse config`.
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(&format!(": {error}"));
+        source = error.source();
     }
+    message
+}
 
+/// Runs the selected subcommand and returns the exit code, see `docs/running.md`.
+async fn run_cli() -> Result<u8, MoorenewError> {
     let args = Command::new("moorenew")
+        .arg(
+            arg!(--config <path> "The configuration file to use. Defaults to $MOORENEW_CONFIG, ~/.moorenew/config.toml, $XDG_CONFIG_HOME/moorenew/config.toml or /etc/moorenew/config.toml")
+                .value_parser(value_parser!(PathBuf))
+                .global(true)
+        )
+        .subcommand(
+            Command::new("init")
+                .about("Create the configuration file interactively")
+                .args([
+                    arg!(--defaults "Don't ask, use the passed values and the defaults for everything else"),
+                    arg!(-f --force "Overwrite an existing configuration file"),
+                    arg!(--host <host> "The host serving the certificates"),
+                    arg!(--port <port> "The SSH port of the host").value_parser(value_parser!(u16)),
+                    arg!(--user <user> "The SSH user"),
+                    arg!(--"remote-cert-path" <path> "The remote directory containing fullchain.pem and privkey.pem"),
+                    arg!(--"mailcow-dir" <path> "The mailcow install directory"),
+                    arg!(--key <path> "The SSH private key, generated if it does not exist"),
+                ])
+        )
         .subcommand(
             Command::new("keygen")
                 .about("Generate a SSH Keypair which moorenew uses to fetch the certificates")
@@ -44,9 +89,10 @@ async fn main() -> Result<(), MoorenewError> {
                 .subcommand(
                     Command::new("setup")
                         .about("Used to create the needed service setup files")
-                        .arg(
-                            arg!(-f --force "Forcefully overwrite existing files")
-                        )
+                        .args([
+                            arg!(-f --force "Forcefully overwrite existing files"),
+                            arg!(-p --provider <provider> "The service provider to create the files for. Can be systemd or openrc. Detected automatically if not set"),
+                        ])
                 )
         )
         .subcommand(
@@ -56,32 +102,107 @@ async fn main() -> Result<(), MoorenewError> {
                     arg!(-d --dry "Don't actually update the certificates, just print what would happen")
                 )
         )
+        .subcommand(
+            Command::new("check-expiry")
+                .about("Check when the installed and the source certificates expire and warn about the ones expiring soon")
+        )
+        .subcommand(
+            Command::new("status")
+                .about("Print the last run, the failures and the installed certificate of every job")
+                .arg(arg!(--json "Print the status as JSON instead of a table"))
+        )
+        .subcommand(
+            Command::new("history")
+                .about("List the certificate replacements, container restarts and failed runs")
+                .args([
+                    arg!(-j --job <job> "Only list runs of this job"),
+                    arg!(--since <date> "Only list runs since this date, e.g. 2026-10-01 or 2026-10-01T12:00:00Z")
+                        .value_parser(parse_since),
+                    arg!(--fingerprint <fingerprint> "Only list runs which replaced or installed the certificate with this fingerprint prefix"),
+                    arg!(-n --limit <count> "Only list the last runs").value_parser(value_parser!(usize)),
+                    arg!(--json "Print the history as JSON lines instead of text"),
+                ])
+        )
+        .subcommand(
+            Command::new("rollback")
+                .about("Restore the last backed up certificates and restart the containers")
+                .args([
+                    arg!(-j --job <job> "The job to roll back. Required if more than one job is configured"),
+                    arg!(--clear "Don't roll back, allow the next run to install the rolled back certificates again"),
+                ])
+        )
         .subcommand(
             Command::new("config")
-                .about("Edit the moorenew configuration file")
+                .about("Edit the moorenew configuration file and validate it afterwards")
+                .subcommand(
+                    Command::new("validate")
+                        .about("Check the configuration file and print every problem found")
+                )
         )
         .subcommand_required(true)
         .arg_required_else_help(true)
         .get_matches();
 
-    if args.subcommand_matches("config").is_some() {
-        match edit::edit_file(config_path.as_os_str()) {
-            Ok(_) => {
-                info!("successfully edited config file");
-            }
-            Err(_) => {
-                error!("failed to edit config file");
+    let config_path = config_path(args.get_one::<PathBuf>("config").map(PathBuf::as_path));
+
+    if let Some(args) = args.subcommand_matches("init") {
+        logging::setup_basic_logging(LevelFilter::INFO);
+        let options = InitOptions {
+            defaults: args.get_flag("defaults"),
+            force: args.get_flag("force"),
+            sftp_host: args.get_one::<String>("host").cloned(),
+            sftp_port: args.get_one::<u16>("port").copied(),
+            sftp_user: args.get_one::<String>("user").cloned(),
+            npm_cert_path: args.get_one::<String>("remote-cert-path").cloned(),
+            mailcow_dir: args.get_one::<String>("mailcow-dir").cloned(),
+            private_key_path: args.get_one::<String>("key").cloned(),
+        };
+        let mut prompt = Prompt::new(std::io::stdin().lock(), std::io::stdout(), options.defaults);
+        init::run_init(&mut prompt, &options, &config_path)?;
+        return Ok(EXIT_SUCCESS);
+    }
+
+    // keygen and service setup work without a configuration
+    if !config_path.exists()
+        && matches!(
+            args.subcommand_name(),
+            Some("config" | "run" | "check-expiry" | "status" | "history" | "rollback")
+        )
+    {
+        return Err(MoorenewError::ConfigurationError(
+            ConfigurationError::ConfigFileMissing(config_path.display().to_string()),
+        ));
+    }
+
+    if let Some(args) = args.subcommand_matches("config") {
+        logging::setup_basic_logging(LevelFilter::INFO);
+        if args.subcommand_matches("validate").is_none() {
+            match edit::edit_file(config_path.as_os_str()) {
+                Ok(_) => {
+                    info!("successfully edited config file");
+                }
+                Err(_) => {
+                    error!("failed to edit config file");
+                }
             }
         }
-    }
 
-    let configuration = match read_config_from_file() {
-        Ok(configuration) => configuration,
-        Err(e) => {
-            error!(error = %e, "failed to read configuration");
-            exit(1);
+        let problems = validate_config(&config_path, std::env::vars());
+        for problem in &problems {
+            error!(field = %problem.field, "{}", problem.message);
         }
-    };
+        if !problems.is_empty() {
+            return Err(MoorenewError::ConfigurationError(
+                ConfigurationError::ValidationFailed {
+                    problems: problems.len(),
+                },
+            ));
+        }
+        info!(config = %config_path.display(), "configuration is valid");
+        return Ok(EXIT_SUCCESS);
+    }
+
+    let mut exit_code = EXIT_SUCCESS;
 
     if let Some(args) = args.subcommand_matches("keygen") {
         logging::setup_basic_logging(LevelFilter::INFO);
@@ -113,13 +234,13 @@ async fn main() -> Result<(), MoorenewError> {
             algorithm = "ed25519";
         }
 
-        sshkeygen::generate_rsa_keypair(
-            algorithm,
-            filename,
-            &comment,
-            &configuration.sftp_host,
-            &configuration.sftp_port,
-        )?;
+        let configuration = read_config_from_file(&config_path).ok();
+        let (host, port) = match configuration.as_ref().and_then(|c| c.jobs.first()) {
+            Some(job) => (job.sftp_host.as_str(), job.sftp_port),
+            None => ("<sftp host>", 22),
+        };
+
+        sshkeygen::generate_rsa_keypair(algorithm, filename, &comment, host, &port)?;
     }
 
     if let Some(subcommand) = args.subcommand_matches("service")
@@ -127,12 +248,22 @@ async fn main() -> Result<(), MoorenewError> {
     {
         logging::setup_basic_logging(LevelFilter::DEBUG);
         let force = args.get_flag("force");
-        match system::service::create_service_files("moorenew", ServiceProvider::SYSTEMD, force) {
+        let service_provider = match args.get_one::<String>("provider") {
+            Some(name) => ServiceProvider::from_name(name)
+                .ok_or_else(|| MoorenewError::UnknownServiceProvider(name.to_owned()))?,
+            None => ServiceProvider::detect().ok_or(MoorenewError::ServiceProviderUndetectable)?,
+        };
+        match service::create_service_files("moorenew", service_provider, force) {
             Ok(_) => {
                 info!("successfully created service files");
-                info!(
-                    "move them to /etc/systemd/system and start each service with systemctl start moorenew.service/moorenew.timer"
-                );
+                match service_provider {
+                    ServiceProvider::SYSTEMD => info!(
+                        "move them to /etc/systemd/system and start each service with systemctl start moorenew.service/moorenew.timer"
+                    ),
+                    ServiceProvider::RC => info!(
+                        "move moorenew.initd to /etc/init.d/moorenew and moorenew.periodic to /etc/periodic/hourly/moorenew, then enable them with rc-update add moorenew default and rc-update add crond default"
+                    ),
+                }
             }
             Err(e) => {
                 if let MoorenewError::ServiceConfigGenerationFailed { components } = &e {
@@ -143,7 +274,9 @@ async fn main() -> Result<(), MoorenewError> {
     }
 
     if let Some(args) = args.subcommand_matches("run") {
-        if let Err(e) = logging::setup_run_logging(&configuration.logging.level, &configuration) {
+        let moorenew = Moorenew::from_config(read_config_from_file(&config_path)?)?;
+        let configuration = moorenew.configuration();
+        if let Err(e) = logging::setup_run_logging(&configuration.logging.level, configuration) {
             notify_buzz_urls(
                 &configuration.buzz_urls,
                 &format!("an error occurred while setting up the logging: {}", e),
@@ -157,19 +290,130 @@ async fn main() -> Result<(), MoorenewError> {
         } else {
             info!("running in normal mode");
         }
-        if let Err(e) = update_certificates(dry_run, &configuration).await {
+        let result = update_certificates(dry_run, &moorenew).await;
+        // the installed certificates are checked even if the update failed, a failing source is
+        // exactly what the warnings are for
+        if !dry_run && let Err(e) = check_expiry(&moorenew).await {
+            warn!(error = %e, "could not check the certificate expiry");
+        }
+        match result {
+            Ok(run_exit_code) => exit_code = run_exit_code,
+            Err(e) => {
+                // failed jobs are already part of the notification sent by update_certificates
+                if !matches!(e, MoorenewError::JobsFailed { .. }) {
+                    notify_buzz_urls(
+                        &configuration.buzz_urls,
+                        &format!("an error occurred while updating certificates: {}", e),
+                    )
+                    .await;
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    if args.subcommand_matches("check-expiry").is_some() {
+        let moorenew = Moorenew::from_config(read_config_from_file(&config_path)?)?;
+        let configuration = moorenew.configuration();
+        logging::setup_run_logging(&configuration.logging.level, configuration)?;
+        exit_code = check_expiry(&moorenew).await?;
+    }
+
+    if let Some(args) = args.subcommand_matches("status") {
+        let moorenew = Moorenew::from_config(read_config_from_file(&config_path)?)?;
+        let mut state = State::load(moorenew.state_dir())?;
+        let statuses: Vec<JobStatus> = moorenew
+            .jobs()
+            .iter()
+            .map(|job| JobStatus {
+                name: job.name().to_string(),
+                state: state.jobs.remove(job.name()).unwrap_or_default(),
+            })
+            .collect();
+
+        if args.get_flag("json") {
+            let json =
+                serde_json::to_string_pretty(&statuses).map_err(|e| MoorenewError::StateFile {
+                    path: State::path(moorenew.state_dir()).display().to_string(),
+                    error: std::io::Error::other(e),
+                })?;
+            println!("{json}");
+        } else {
+            println!("{}", format_status_table(&statuses));
+        }
+    }
+
+    if let Some(args) = args.subcommand_matches("history") {
+        let moorenew = Moorenew::from_config(read_config_from_file(&config_path)?)?;
+        let filter = HistoryFilter {
+            job: args.get_one::<String>("job").cloned(),
+            since: args.get_one::<DateTime<Utc>>("since").copied(),
+            fingerprint: args.get_one::<String>("fingerprint").cloned(),
+            limit: args.get_one::<usize>("limit").copied(),
+        };
+        let entries = filter.apply(history::load(moorenew.state_dir())?);
+
+        if args.get_flag("json") {
+            for entry in &entries {
+                let json = serde_json::to_string(entry).map_err(|e| MoorenewError::StateFile {
+                    path: history::history_path(moorenew.state_dir())
+                        .display()
+                        .to_string(),
+                    error: std::io::Error::other(e),
+                })?;
+                println!("{json}");
+            }
+        } else if !entries.is_empty() {
+            println!("{}", format_history(&entries));
+        }
+    }
+
+    if let Some(args) = args.subcommand_matches("rollback") {
+        logging::setup_basic_logging(LevelFilter::INFO);
+        let moorenew = Moorenew::from_config(read_config_from_file(&config_path)?)?;
+        let configuration = moorenew.configuration();
+        let job = moorenew.job(args.get_one::<String>("job").map(String::as_str))?;
+
+        if args.get_flag("clear") {
+            let mut state = State::load(moorenew.state_dir())?;
+            let cleared = state.clear_rolled_back(job.name());
+            state.save(moorenew.state_dir())?;
+            info!(
+                job = job.name(),
+                certificates = cleared.len(),
+                "cleared the rolled back certificates"
+            );
+            return Ok(exit_code);
+        }
+
+        let failed_containers = job.rollback()?.failed_containers;
+        if !failed_containers.is_empty() {
             notify_buzz_urls(
                 &configuration.buzz_urls,
-                &format!("an error occurred while updating certificates: {}", e),
+                &format!(
+                    "{}: certificates rolled back, but not all containers could be restarted ({})",
+                    job.name(),
+                    failed_containers.join(", ")
+                ),
             )
             .await;
-            return Err(e);
+            exit_code = EXIT_CONTAINERS_FAILED;
         }
     }
 
-    sleep(Duration::from_millis(10)).await;
+    Ok(exit_code)
+}
 
-    Ok(())
+/// Parses `--since` as an RFC 3339 time or as a date, which means midnight UTC.
+fn parse_since(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| time.and_utc())
+        .ok_or_else(|| format!("{value} is neither a date nor an RFC 3339 time"))
 }
 
 async fn notify_buzz_urls(urls: &[String], message: &str) {
@@ -178,92 +422,162 @@ async fn notify_buzz_urls(urls: &[String], message: &str) {
     }
 }
 
-#[instrument(fields(result), skip(configuration))]
-async fn update_certificates(
-    dry_run: bool,
-    configuration: &Configuration,
-) -> Result<(), MoorenewError> {
-    let username = &configuration.sftp_user;
-    let host = &configuration.sftp_host;
-    let port = &configuration.sftp_port;
-    let private_key_path = &configuration.private_key_path;
-    let public_key_path = &configuration.public_key_path;
-
-    // Download certificates
-
-    let client = SSHClient::connect(username, host, port, private_key_path, public_key_path)
-        .map_err(MoorenewError::SSHConnectError)?;
-
-    download_certificates(
-        &client,
-        Path::new(&configuration.mail_cert_path),
-        Path::new(&configuration.npm_cert_path),
-        dry_run,
-    )?;
-
-    let mut err_count = 0;
-
-    if !dry_run {
-        configuration
-            .containers
+/// Checks the expiry of the certificates of every job and sends a warning for every certificate
+/// which reached one of the `expiry_thresholds` since the last check. Returns [`EXIT_EXPIRING`] if
+/// any certificate expires within the highest threshold.
+#[instrument(fields(result), skip(moorenew))]
+async fn check_expiry(moorenew: &Moorenew) -> Result<u8, MoorenewError> {
+    let configuration = moorenew.configuration();
+    let thresholds = &configuration.expiry_thresholds;
+    let highest_threshold = thresholds.iter().max().map(|threshold| *threshold as i32);
+    let mut alerts = ExpiryAlerts::load(moorenew.state_dir());
+    let mut warnings = Vec::new();
+    let mut expiring = false;
+
+    for job in moorenew.jobs() {
+        let expiries = job.check_expiry();
+        expiring |= expiries
             .iter()
-            .for_each(|container| match restart_container(container) {
-                Ok(_) => {
-                    info!("successfully restarted {}", container);
-                }
-                Err(e) => {
-                    error!("failed to restart {}: {:?}", container, e);
-                    err_count += 1;
-                }
-            });
+            .flat_map(|expiry| [&expiry.installed, &expiry.source])
+            .flatten()
+            .any(|expiry| highest_threshold.is_some_and(|threshold| expiry.days <= threshold));
+        warnings.extend(expiry_warnings(
+            job.name(),
+            &expiries,
+            thresholds,
+            &mut alerts,
+        ));
     }
 
-    if err_count == 0 {
-        tracing::Span::current().record("result", "success");
+    if !warnings.is_empty() {
         notify_buzz_urls(
             &configuration.buzz_urls,
-            "mailcow certificate renewal was successful",
-        )
-        .await;
-    } else {
-        tracing::Span::current().record("result", "partially successful");
-        notify_buzz_urls(
-            &configuration.buzz_urls,
-            "not all containers could be restarted",
+            &format!("mailcow certificate expiry:\n{}", warnings.join("\n")),
         )
         .await;
     }
+    alerts.save()?;
 
-    info!("finished update process. see result field for more details");
+    let result = if expiring { "expiring" } else { "ok" };
+    tracing::Span::current().record("result", result);
+    info!("finished expiry check. see result field for more details");
 
-    client.disconnect();
-    Ok(())
+    Ok(if expiring {
+        EXIT_EXPIRING
+    } else {
+        EXIT_SUCCESS
+    })
 }
 
-fn restart_container(container_name: &str) -> anyhow::Result<Output> {
-    let output = std::process::Command::new("docker")
-        .args(["ps", "-qaf", &format!("name={}", container_name)])
-        .output()?;
-
-    if !output.status.success() {
-        return Err(anyhow::anyhow!(
-            "error searching container id: {}",
-            String::from_utf8_lossy(&output.stderr)
+/// Runs every job and returns [`EXIT_SUCCESS`] if certificates were updated,
+/// [`EXIT_UP_TO_DATE`] if nothing changed and [`EXIT_CONTAINERS_FAILED`] if certificates were
+/// installed, but not every container picked them up.
+#[instrument(fields(result), skip(moorenew))]
+async fn update_certificates(dry_run: bool, moorenew: &Moorenew) -> Result<u8, MoorenewError> {
+    let configuration = moorenew.configuration();
+    if configuration.jobs.is_empty() {
+        return Err(MoorenewError::ConfigurationError(
+            ConfigurationError::NoJobsConfigured,
         ));
     }
 
-    let container_id = String::from_utf8(output.stdout)?.trim().to_string();
+    let mut report: Vec<String> = Vec::new();
+    let mut failed_jobs: Vec<String> = Vec::new();
+    let mut failed_exit_code = None;
+    let mut updated_jobs = 0;
+    let mut partially_updated_jobs = 0;
 
-    if container_id.is_empty() {
-        return Err(anyhow::anyhow!(
-            "no container with name {} found",
-            container_name
-        ));
+    for job in moorenew.jobs() {
+        let outcome = if dry_run {
+            job.plan().map(|plan| match plan {
+                Plan::UpToDate => JobOutcome::UpToDate,
+                Plan::Update {
+                    certificate,
+                    domains,
+                    removed_domains,
+                } => JobOutcome::Updated {
+                    certificate,
+                    domains,
+                    removed_domains,
+                    failed_containers: Vec::new(),
+                    tls_verification: None,
+                },
+            })
+        } else {
+            job.apply()
+        };
+
+        match outcome {
+            Ok(JobOutcome::UpToDate) => {}
+            Ok(JobOutcome::Updated {
+                domains,
+                removed_domains,
+                failed_containers,
+                tls_verification,
+                ..
+            }) => {
+                updated_jobs += 1;
+                if failed_containers.is_empty() {
+                    let mut line = format!("{}: certificate renewal was successful", job.name());
+                    if !domains.is_empty() {
+                        line.push_str(&format!(", updated domains {}", domains.join(", ")));
+                    }
+                    if !removed_domains.is_empty() {
+                        line.push_str(&format!(", removed domains {}", removed_domains.join(", ")));
+                    }
+                    if let Some(tls_verification) = tls_verification {
+                        line.push_str(&format!(", {tls_verification}"));
+                    }
+                    report.push(line);
+                } else {
+                    partially_updated_jobs += 1;
+                    report.push(format!(
+                        "{}: not all containers could be restarted ({})",
+                        job.name(),
+                        failed_containers.join(", ")
+                    ));
+                }
+            }
+            Err(e) => {
+                report.push(format!("{}: an error occurred: {}", job.name(), e));
+                failed_jobs.push(job.name().to_string());
+                failed_exit_code.get_or_insert(e.exit_code());
+            }
+        }
+    }
+    let result = if !failed_jobs.is_empty() {
+        "failed"
+    } else if partially_updated_jobs > 0 {
+        "partially successful"
+    } else if updated_jobs == 0 {
+        "up to date"
+    } else {
+        "success"
+    };
+    tracing::Span::current().record("result", result);
+
+    if !report.is_empty() {
+        notify_buzz_urls(
+            &configuration.buzz_urls,
+            &format!("mailcow certificate renewal:\n{}", report.join("\n")),
+        )
+        .await;
     }
 
-    let restart_output = std::process::Command::new("docker")
-        .args(["restart", &container_id])
-        .output()?;
+    info!("finished update process. see result field for more details");
 
-    Ok(restart_output)
+    if let Some(exit_code) = failed_exit_code {
+        return Err(MoorenewError::JobsFailed {
+            jobs: failed_jobs,
+            exit_code,
+        });
+    }
+
+    if partially_updated_jobs > 0 {
+        Ok(EXIT_CONTAINERS_FAILED)
+    } else if updated_jobs == 0 {
+        Ok(EXIT_UP_TO_DATE)
+    } else {
+        Ok(EXIT_SUCCESS)
+    }
 }
@@ -3,6 +3,7 @@ use crate::system::sysinfo::get_binary_path;
 use crate::utils::errors::MoorenewError;
 use std::fs::File;
 use std::io::{Error, Write};
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::string::String;
 use tracing::{debug, error};
@@ -33,7 +34,20 @@ pub fn create_service_files(
             }
         }
         ServiceProvider::RC => {
-            // TODO: Implement RC file configurations
+            match create_openrc_init_file(service_name, force) {
+                Ok(_) => {}
+                Err(_) => {
+                    errored = true;
+                    errored_creations.push("init script".to_string());
+                }
+            }
+            match create_openrc_periodic_file(service_name, force) {
+                Ok(_) => {}
+                Err(_) => {
+                    errored = true;
+                    errored_creations.push("periodic script".to_string());
+                }
+            }
         }
     }
 
@@ -123,6 +137,7 @@ Restart=on-failure
 RestartSec=1
 User=root
 ExecStart={binary_path} run
+SuccessExitStatus=10
 
 [Install]
 WantedBy=multi-user.target\n"
@@ -142,3 +157,96 @@ WantedBy=multi-user.target\n"
     debug!(file = %service_file_name, "Service file created successfully");
     Ok(())
 }
+
+fn create_openrc_init_file(service_name: &str, force: bool) -> Result<(), MoorenewError> {
+    let binary_path = get_binary_path_or_placeholder(&format!("{service_name}.initd"));
+
+    let init_file_string = format!(
+        "#!/sbin/openrc-run
+
+name=\"{service_name}\"
+description=\"updates the ssl certificates for mailcow\"
+command=\"{binary_path}\"
+command_args=\"run\"
+
+depend() {{
+\tneed net
+\tafter docker
+}}
+
+start() {{
+\tebegin \"Updating mailcow certificates\"
+\t${{command}} ${{command_args}}
+\tret=$?
+\t# exit code 10 means the certificates are already up to date
+\t[ \"$ret\" -eq 10 ] && ret=0
+\teend $ret
+}}\n"
+    );
+
+    write_script_file(&format!("{service_name}.initd"), &init_file_string, force)
+        .map_err(MoorenewError::InitScriptCreationFailed)?;
+
+    debug!(file = %format!("{service_name}.initd"), "Init script created successfully");
+    Ok(())
+}
+
+/// Creates the script for `/etc/periodic/hourly` of crond. OpenRC therefore always runs hourly,
+/// the interval of the systemd timer does not apply to it.
+fn create_openrc_periodic_file(service_name: &str, force: bool) -> Result<(), MoorenewError> {
+    let binary_path = get_binary_path_or_placeholder(&format!("{service_name}.periodic"));
+
+    let periodic_file_string = format!(
+        "#!/bin/sh
+# Copying SSL certificates for mailcow
+{binary_path} run
+ret=$?
+# exit code 10 means the certificates are already up to date
+[ \"$ret\" -eq 10 ] && ret=0
+exit $ret\n"
+    );
+
+    write_script_file(
+        &format!("{service_name}.periodic"),
+        &periodic_file_string,
+        force,
+    )
+    .map_err(MoorenewError::PeriodicScriptCreationFailed)?;
+
+    debug!(file = %format!("{service_name}.periodic"), "Periodic script created successfully");
+    Ok(())
+}
+
+fn get_binary_path_or_placeholder(file_name: &str) -> String {
+    match get_binary_path() {
+        Ok(path) => path,
+        Err(_) => {
+            error!("Could not get binary path, please set it manually in the {file_name} file");
+            "<set binary path here>".to_string()
+        }
+    }
+}
+
+fn write_script_file(file_name: &str, contents: &str, force: bool) -> Result<(), Error> {
+    if Path::new(file_name).exists() && !force {
+        let msg = "file already exists. run with -f flag to overwrite";
+        error!(file = %file_name, error = msg, "Could not create script file");
+        return Err(Error::new(std::io::ErrorKind::AlreadyExists, msg));
+    }
+
+    let mut file = File::create(file_name).map_err(|e| {
+        error!(error = %e, file = %file_name, "Could not create script file");
+        e
+    })?;
+
+    file.write_all(contents.as_bytes()).map_err(|e| {
+        error!(error = %e, file = %file_name, "Could not write to script file");
+        e
+    })?;
+
+    file.set_permissions(std::fs::Permissions::from_mode(0o755))
+        .map_err(|e| {
+            error!(error = %e, file = %file_name, "Could not make script file executable");
+            e
+        })
+}
@@ -1,6 +1,56 @@
-#[allow(dead_code)]
+use std::path::Path;
+
 #[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ServiceProvider {
     SYSTEMD,
     RC,
 }
+
+impl ServiceProvider {
+    /// from_name maps the name passed via `--provider` to a service provider.
+    pub fn from_name(name: &str) -> Option<ServiceProvider> {
+        match &*name.to_lowercase() {
+            "systemd" => Some(ServiceProvider::SYSTEMD),
+            "openrc" | "rc" => Some(ServiceProvider::RC),
+            _ => None,
+        }
+    }
+
+    /// detect returns the service provider of the running system, if it can be determined.
+    pub fn detect() -> Option<ServiceProvider> {
+        detect_in(Path::new("/"))
+    }
+}
+
+fn detect_in(root: &Path) -> Option<ServiceProvider> {
+    // same check as sd_booted(3)
+    if root.join("run/systemd/system").is_dir() {
+        return Some(ServiceProvider::SYSTEMD);
+    }
+
+    if root.join("run/openrc").is_dir() || root.join("sbin/openrc-run").exists() {
+        return Some(ServiceProvider::RC);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ServiceProvider, detect_in};
+    use crate::utils::testutil::TempDir;
+    use std::fs;
+
+    #[test]
+    fn test_detect_service_provider() {
+        let root = TempDir::new("detect");
+        assert_eq!(detect_in(&root), None);
+
+        fs::create_dir_all(root.join("run/openrc")).unwrap();
+        assert_eq!(detect_in(&root), Some(ServiceProvider::RC));
+
+        fs::create_dir_all(root.join("run/systemd/system")).unwrap();
+        assert_eq!(detect_in(&root), Some(ServiceProvider::SYSTEMD));
+    }
+}
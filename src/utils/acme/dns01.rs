@@ -0,0 +1,361 @@
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info};
+
+use super::ChallengeSolver;
+use super::jws::base64url;
+use crate::utils::errors::{AcmeError, ConfigurationError, MoorenewError};
+
+const OPCODE_UPDATE: u16 = 5;
+const TYPE_SOA: u16 = 6;
+const TYPE_TXT: u16 = 16;
+const TYPE_TSIG: u16 = 250;
+const CLASS_IN: u16 = 1;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+
+/// Allowed difference between the clocks of moorenew and the nameserver.
+const TSIG_FUDGE: u16 = 300;
+
+/// Read and write timeout of update requests.
+const UPDATE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Key authenticating dynamic updates with TSIG (RFC 8945).
+pub struct TsigKey {
+    name: String,
+    algorithm: &'static str,
+    digest: MessageDigest,
+    secret: Vec<u8>,
+}
+
+impl TsigKey {
+    /// Creates a key from its name, an algorithm like `hmac-sha256` and the base64 encoded secret
+    /// as printed by `tsig-keygen`.
+    pub fn new(name: &str, algorithm: &str, secret: &str) -> Result<TsigKey, MoorenewError> {
+        let invalid = |reason: String| {
+            MoorenewError::ConfigurationError(ConfigurationError::InvalidTsigKey(reason))
+        };
+
+        let (algorithm, digest) = match algorithm.trim_end_matches('.').to_lowercase().as_str() {
+            "hmac-sha1" => ("hmac-sha1.", MessageDigest::sha1()),
+            "hmac-sha256" => ("hmac-sha256.", MessageDigest::sha256()),
+            "hmac-sha384" => ("hmac-sha384.", MessageDigest::sha384()),
+            "hmac-sha512" => ("hmac-sha512.", MessageDigest::sha512()),
+            other => return Err(invalid(format!("unsupported algorithm `{other}`"))),
+        };
+        let secret = BASE64_STANDARD
+            .decode(secret.trim())
+            .map_err(|e| invalid(format!("secret is not valid base64: {e}")))?;
+        if name.is_empty() || secret.is_empty() {
+            return Err(invalid("name and secret must not be empty".to_string()));
+        }
+
+        Ok(TsigKey {
+            name: name.to_lowercase(),
+            algorithm,
+            digest,
+            secret,
+        })
+    }
+}
+
+/// Answers DNS-01 challenges by creating the `_acme-challenge` TXT records with RFC 2136 dynamic
+/// updates, e.g. on a BIND or Knot primary.
+pub struct Dns01Solver {
+    nameserver: String,
+    zone: String,
+    key: TsigKey,
+    ttl: u32,
+    propagation_delay: Duration,
+}
+
+impl Dns01Solver {
+    pub fn new(
+        nameserver: String,
+        zone: String,
+        key: TsigKey,
+        ttl: u32,
+        propagation_delay: Duration,
+    ) -> Dns01Solver {
+        Dns01Solver {
+            nameserver,
+            zone,
+            key,
+            ttl,
+            propagation_delay,
+        }
+    }
+
+    fn update(
+        &self,
+        identifier: &str,
+        key_authorization: &str,
+        add: bool,
+    ) -> Result<(), AcmeError> {
+        let record = format!("_acme-challenge.{identifier}");
+        let value = base64url(&Sha256::digest(key_authorization.as_bytes()));
+
+        let mut id = [0; 2];
+        openssl::rand::rand_bytes(&mut id).map_err(AcmeError::OpenSsl)?;
+        let id = u16::from_be_bytes(id);
+        let time_signed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut message = update_message(id, &self.zone, &record, &value, self.ttl, add);
+        sign_message(&mut message, &self.key, time_signed)?;
+
+        let rcode = send_message(&self.nameserver, &message, id).map_err(AcmeError::Challenge)?;
+        if rcode != 0 {
+            return Err(AcmeError::DnsUpdate { record, rcode });
+        }
+        debug!(record, add, "updated challenge record");
+
+        Ok(())
+    }
+}
+
+impl ChallengeSolver for Dns01Solver {
+    fn challenge_type(&self) -> &'static str {
+        "dns-01"
+    }
+
+    fn present(
+        &mut self,
+        identifier: &str,
+        _token: &str,
+        key_authorization: &str,
+    ) -> Result<(), AcmeError> {
+        self.update(identifier, key_authorization, true)
+    }
+
+    fn ready(&mut self) -> Result<(), AcmeError> {
+        info!(
+            seconds = self.propagation_delay.as_secs(),
+            "waiting for the challenge records to propagate"
+        );
+        std::thread::sleep(self.propagation_delay);
+        Ok(())
+    }
+
+    fn cleanup(
+        &mut self,
+        identifier: &str,
+        _token: &str,
+        key_authorization: &str,
+    ) -> Result<(), AcmeError> {
+        self.update(identifier, key_authorization, false)
+    }
+}
+
+/// Builds an UPDATE message adding the TXT record `record` with `value` to `zone`, or deleting
+/// exactly this record if `add` is false.
+fn update_message(id: u16, zone: &str, record: &str, value: &str, ttl: u32, add: bool) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend(id.to_be_bytes());
+    message.extend((OPCODE_UPDATE << 11).to_be_bytes());
+    // one zone, no prerequisites, one update, no additional records
+    for count in [1u16, 0, 1, 0] {
+        message.extend(count.to_be_bytes());
+    }
+
+    encode_name(zone, &mut message);
+    message.extend(TYPE_SOA.to_be_bytes());
+    message.extend(CLASS_IN.to_be_bytes());
+
+    let (class, ttl) = if add {
+        (CLASS_IN, ttl)
+    } else {
+        (CLASS_NONE, 0)
+    };
+    encode_name(record, &mut message);
+    message.extend(TYPE_TXT.to_be_bytes());
+    message.extend(class.to_be_bytes());
+    message.extend(ttl.to_be_bytes());
+    // a key authorization digest always fits into a single character string
+    message.extend((value.len() as u16 + 1).to_be_bytes());
+    message.push(value.len() as u8);
+    message.extend(value.as_bytes());
+
+    message
+}
+
+/// Appends a TSIG record to `message` and increments its additional record count.
+fn sign_message(message: &mut Vec<u8>, key: &TsigKey, time_signed: u64) -> Result<(), AcmeError> {
+    let time_signed = &time_signed.to_be_bytes()[2..];
+    let id = [message[0], message[1]];
+
+    let mut variables = Vec::new();
+    encode_name(&key.name, &mut variables);
+    variables.extend(CLASS_ANY.to_be_bytes());
+    variables.extend(0u32.to_be_bytes());
+    encode_name(key.algorithm, &mut variables);
+    variables.extend(time_signed);
+    variables.extend(TSIG_FUDGE.to_be_bytes());
+    // no error and no other data
+    variables.extend([0, 0, 0, 0]);
+
+    let hmac_key = PKey::hmac(&key.secret).map_err(AcmeError::OpenSsl)?;
+    let mut signer = Signer::new(key.digest, &hmac_key).map_err(AcmeError::OpenSsl)?;
+    signer.update(message).map_err(AcmeError::OpenSsl)?;
+    signer.update(&variables).map_err(AcmeError::OpenSsl)?;
+    let mac = signer.sign_to_vec().map_err(AcmeError::OpenSsl)?;
+
+    let mut rdata = Vec::new();
+    encode_name(key.algorithm, &mut rdata);
+    rdata.extend(time_signed);
+    rdata.extend(TSIG_FUDGE.to_be_bytes());
+    rdata.extend((mac.len() as u16).to_be_bytes());
+    rdata.extend(&mac);
+    rdata.extend(id);
+    rdata.extend([0, 0, 0, 0]);
+
+    encode_name(&key.name, message);
+    message.extend(TYPE_TSIG.to_be_bytes());
+    message.extend(CLASS_ANY.to_be_bytes());
+    message.extend(0u32.to_be_bytes());
+    message.extend((rdata.len() as u16).to_be_bytes());
+    message.extend(rdata);
+
+    let additional = u16::from_be_bytes([message[10], message[11]]) + 1;
+    message[10..12].copy_from_slice(&additional.to_be_bytes());
+    Ok(())
+}
+
+/// Sends `message` over TCP and returns the response code of the answer. The TSIG record of the
+/// answer is not verified, a forged success only makes the ACME validation fail.
+fn send_message(nameserver: &str, message: &[u8], id: u16) -> std::io::Result<u8> {
+    let mut stream = TcpStream::connect(nameserver)?;
+    stream.set_read_timeout(Some(UPDATE_TIMEOUT))?;
+    stream.set_write_timeout(Some(UPDATE_TIMEOUT))?;
+
+    let mut request = (message.len() as u16).to_be_bytes().to_vec();
+    request.extend(message);
+    stream.write_all(&request)?;
+
+    let mut length = [0; 2];
+    stream.read_exact(&mut length)?;
+    let mut response = vec![0; usize::from(u16::from_be_bytes(length))];
+    stream.read_exact(&mut response)?;
+
+    if response.len() < 12 || u16::from_be_bytes([response[0], response[1]]) != id {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "unexpected dns response",
+        ));
+    }
+    Ok(response[3] & 0x0f)
+}
+
+/// Appends `name` in wire format without compression.
+fn encode_name(name: &str, out: &mut Vec<u8>) {
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() {
+            continue;
+        }
+        out.push(label.len() as u8);
+        out.extend(label.as_bytes());
+    }
+    out.push(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dns01Solver, TsigKey, encode_name, update_message};
+    use crate::utils::acme::ChallengeSolver;
+    use crate::utils::errors::AcmeError;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// Answers one update with `rcode` and sends the received message through the channel.
+    fn nameserver(rcode: u8) -> (String, mpsc::Receiver<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut length = [0; 2];
+            stream.read_exact(&mut length).unwrap();
+            let mut message = vec![0; usize::from(u16::from_be_bytes(length))];
+            stream.read_exact(&mut message).unwrap();
+
+            let mut response = message[..12].to_vec();
+            response[2] |= 0x80;
+            response[3] = rcode;
+            stream.write_all(&12u16.to_be_bytes()).unwrap();
+            stream.write_all(&response).unwrap();
+            sender.send(message).unwrap();
+        });
+
+        (address, receiver)
+    }
+
+    fn solver(nameserver: String) -> Dns01Solver {
+        Dns01Solver::new(
+            nameserver,
+            "example.com".to_string(),
+            TsigKey::new("moorenew", "hmac-sha256", "c2VjcmV0c2VjcmV0c2VjcmV0").unwrap(),
+            60,
+            Duration::ZERO,
+        )
+    }
+
+    #[test]
+    fn test_update() {
+        let (address, receiver) = nameserver(0);
+        solver(address)
+            .present("mail.example.com", "token", "token.thumbprint")
+            .unwrap();
+        let message = receiver.recv().unwrap();
+
+        // same message as the unsigned one, apart from the additional record count
+        let id = u16::from_be_bytes([message[0], message[1]]);
+        let value = "61rBZ_4knHblO0MNoxFsXZ_eTFUHum0B6IVRbhvUn5I";
+        let unsigned = update_message(
+            id,
+            "example.com",
+            "_acme-challenge.mail.example.com",
+            value,
+            60,
+            true,
+        );
+        assert_eq!(message[..10], unsigned[..10]);
+        assert_eq!(message[10..12], [0, 1]);
+        assert_eq!(message[12..unsigned.len()], unsigned[12..]);
+
+        let mut tsig = Vec::new();
+        encode_name("moorenew", &mut tsig);
+        tsig.extend([0, 250, 0, 255]);
+        assert_eq!(
+            message[unsigned.len()..unsigned.len() + tsig.len()],
+            tsig[..]
+        );
+    }
+
+    #[test]
+    fn test_update_refused() {
+        let (address, _receiver) = nameserver(5);
+        assert!(matches!(
+            solver(address).cleanup("mail.example.com", "token", "token.thumbprint"),
+            Err(AcmeError::DnsUpdate { rcode: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn test_tsig_key() {
+        assert!(TsigKey::new("moorenew", "hmac-sha512", "c2VjcmV0").is_ok());
+        assert!(TsigKey::new("moorenew", "hmac-md5", "c2VjcmV0").is_err());
+        assert!(TsigKey::new("moorenew", "hmac-sha256", "not base64!").is_err());
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use super::ChallengeSolver;
+use crate::utils::errors::AcmeError;
+
+const CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+
+/// Time the responder waits between checks for new connections and for being stopped.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Read timeout for requests of the validation servers.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Answers HTTP-01 challenges, either with a temporary responder on `listen` or by writing the key
+/// authorizations into the `webroot` of a running web server.
+pub struct Http01Solver {
+    listen: String,
+    webroot: Option<PathBuf>,
+    tokens: Arc<Mutex<HashMap<String, String>>>,
+    responder: Option<Responder>,
+}
+
+/// Temporary HTTP server running until it is dropped.
+struct Responder {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Http01Solver {
+    pub fn new(listen: String, webroot: Option<PathBuf>) -> Http01Solver {
+        Http01Solver {
+            listen,
+            webroot,
+            tokens: Arc::new(Mutex::new(HashMap::new())),
+            responder: None,
+        }
+    }
+
+    fn start_responder(&mut self) -> Result<(), AcmeError> {
+        if self.responder.is_some() {
+            return Ok(());
+        }
+
+        let listener = TcpListener::bind(&self.listen).map_err(AcmeError::Challenge)?;
+        listener
+            .set_nonblocking(true)
+            .map_err(AcmeError::Challenge)?;
+        info!(listen = self.listen, "started http-01 responder");
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            let tokens = self.tokens.clone();
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, peer)) => {
+                            if let Err(e) = respond(stream, &tokens) {
+                                debug!(error = %e, %peer, "could not answer http-01 request");
+                            }
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                            std::thread::sleep(ACCEPT_INTERVAL)
+                        }
+                        Err(e) => {
+                            warn!(error = %e, "could not accept http-01 connection");
+                            std::thread::sleep(ACCEPT_INTERVAL);
+                        }
+                    }
+                }
+            })
+        };
+
+        self.responder = Some(Responder {
+            stop,
+            thread: Some(thread),
+        });
+        Ok(())
+    }
+}
+
+impl ChallengeSolver for Http01Solver {
+    fn challenge_type(&self) -> &'static str {
+        "http-01"
+    }
+
+    fn present(
+        &mut self,
+        identifier: &str,
+        token: &str,
+        key_authorization: &str,
+    ) -> Result<(), AcmeError> {
+        if let Some(webroot) = &self.webroot {
+            let path = webroot.join(token);
+            std::fs::create_dir_all(webroot).map_err(AcmeError::Challenge)?;
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o644)
+                .open(&path)
+                .and_then(|mut file| file.write_all(key_authorization.as_bytes()))
+                .map_err(AcmeError::Challenge)?;
+            debug!(identifier, path = %path.display(), "wrote http-01 challenge");
+            return Ok(());
+        }
+
+        self.tokens
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(token.to_string(), key_authorization.to_string());
+        self.start_responder()
+    }
+
+    fn cleanup(
+        &mut self,
+        _identifier: &str,
+        token: &str,
+        _key_authorization: &str,
+    ) -> Result<(), AcmeError> {
+        if let Some(webroot) = &self.webroot {
+            return std::fs::remove_file(webroot.join(token)).map_err(AcmeError::Challenge);
+        }
+
+        let mut tokens = self.tokens.lock().unwrap_or_else(|e| e.into_inner());
+        tokens.remove(token);
+        if tokens.is_empty() {
+            drop(tokens);
+            self.responder = None;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Responder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Answers a single request with the key authorization of the requested token, or 404.
+fn respond(stream: TcpStream, tokens: &Mutex<HashMap<String, String>>) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let key_authorization = request_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|path| path.strip_prefix(CHALLENGE_PATH))
+        .and_then(|token| {
+            tokens
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(token)
+                .cloned()
+        });
+
+    let response = match key_authorization {
+        Some(key_authorization) => {
+            debug!(request = request_line.trim(), "answering http-01 challenge");
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{key_authorization}",
+                key_authorization.len()
+            )
+        }
+        None => {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+        }
+    };
+    reader.get_mut().write_all(response.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Http01Solver;
+    use crate::utils::acme::ChallengeSolver;
+    use crate::utils::http::request;
+    use crate::utils::testutil::TempDir;
+    use std::net::{TcpListener, TcpStream};
+
+    fn get(port: u16, path: &str) -> (u16, Vec<u8>) {
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let response = request(stream, "GET", "mail.example.com", path, &[], None).unwrap();
+        (response.status, response.body)
+    }
+
+    #[test]
+    fn test_http01_responder() {
+        // reserve a free port for the responder
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut solver = Http01Solver::new(format!("127.0.0.1:{port}"), None);
+
+        solver
+            .present("mail.example.com", "token", "token.thumbprint")
+            .unwrap();
+        assert_eq!(
+            get(port, "/.well-known/acme-challenge/token"),
+            (200, b"token.thumbprint".to_vec())
+        );
+        assert_eq!(get(port, "/.well-known/acme-challenge/other").0, 404);
+
+        solver
+            .cleanup("mail.example.com", "token", "token.thumbprint")
+            .unwrap();
+        assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+    }
+
+    #[test]
+    fn test_http01_webroot() {
+        let root = TempDir::new("webroot");
+        let webroot = root.join(".well-known/acme-challenge");
+        let mut solver = Http01Solver::new(String::new(), Some(webroot.clone()));
+
+        solver
+            .present("mail.example.com", "token", "token.thumbprint")
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(webroot.join("token")).unwrap(),
+            "token.thumbprint"
+        );
+        solver
+            .cleanup("mail.example.com", "token", "token.thumbprint")
+            .unwrap();
+        assert!(!webroot.join("token").exists());
+    }
+}
@@ -0,0 +1,244 @@
+use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::sign::Signer;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::Path;
+use tracing::info;
+
+use crate::utils::errors::AcmeError;
+
+/// Length of the coordinates and signature halves of P-256 keys in bytes.
+const P256_FIELD_LENGTH: i32 = 32;
+
+pub fn base64url(data: &[u8]) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(data)
+}
+
+/// P-256 key of an ACME account, signing requests with ES256.
+pub struct AccountKey {
+    key: PKey<Private>,
+}
+
+impl AccountKey {
+    pub fn generate() -> Result<AccountKey, AcmeError> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).map_err(AcmeError::OpenSsl)?;
+        let key = EcKey::generate(&group)
+            .and_then(PKey::from_ec_key)
+            .map_err(AcmeError::OpenSsl)?;
+
+        Ok(AccountKey { key })
+    }
+
+    /// Reads the PEM encoded key at `path`, or generates one and stores it there readable only by
+    /// the owner. The account is bound to the key, so losing it registers a new account.
+    pub fn load_or_generate(path: &Path) -> Result<AccountKey, AcmeError> {
+        let key_error = |error| AcmeError::AccountKey {
+            path: path.display().to_string(),
+            error,
+        };
+
+        if path.exists() {
+            let pem = std::fs::read(path).map_err(key_error)?;
+            let key = PKey::private_key_from_pem(&pem)
+                .map_err(|e| key_error(std::io::Error::other(e)))?;
+            return Ok(AccountKey { key });
+        }
+
+        let account_key = AccountKey::generate()?;
+        let pem = account_key
+            .key
+            .private_key_to_pem_pkcs8()
+            .map_err(AcmeError::OpenSsl)?;
+        if let Some(parent) = path.parent() {
+            std::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(parent)
+                .map_err(key_error)?;
+        }
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)
+            .and_then(|mut file| file.write_all(&pem))
+            .map_err(key_error)?;
+        info!(path = %path.display(), "generated acme account key");
+
+        Ok(account_key)
+    }
+
+    /// Public key as JSON Web Key with the members in the order required by [`Self::thumbprint`].
+    pub fn jwk(&self) -> Result<Value, AcmeError> {
+        let (x, y) = self.coordinates()?;
+        Ok(json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": base64url(&x),
+            "y": base64url(&y),
+        }))
+    }
+
+    /// RFC 7638 thumbprint of the public key.
+    pub fn thumbprint(&self) -> Result<String, AcmeError> {
+        let (x, y) = self.coordinates()?;
+        let jwk = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            base64url(&x),
+            base64url(&y)
+        );
+        Ok(base64url(&Sha256::digest(jwk.as_bytes())))
+    }
+
+    /// Key authorization proving control over the account key for a challenge `token`.
+    pub fn key_authorization(&self, token: &str) -> Result<String, AcmeError> {
+        Ok(format!("{token}.{}", self.thumbprint()?))
+    }
+
+    /// Signs `payload` and returns the request body in the flattened JWS JSON serialization. An
+    /// empty payload is used for POST-as-GET requests.
+    pub fn sign(&self, protected: &Value, payload: &str) -> Result<Value, AcmeError> {
+        let protected = base64url(protected.to_string().as_bytes());
+        let payload = base64url(payload.as_bytes());
+
+        let mut signer =
+            Signer::new(MessageDigest::sha256(), &self.key).map_err(AcmeError::OpenSsl)?;
+        signer
+            .update(format!("{protected}.{payload}").as_bytes())
+            .map_err(AcmeError::OpenSsl)?;
+        let der = signer.sign_to_vec().map_err(AcmeError::OpenSsl)?;
+
+        // JWS uses the fixed size concatenation of r and s instead of the DER structure
+        let signature = EcdsaSig::from_der(&der).map_err(AcmeError::OpenSsl)?;
+        let mut raw = signature
+            .r()
+            .to_vec_padded(P256_FIELD_LENGTH)
+            .map_err(AcmeError::OpenSsl)?;
+        raw.extend(
+            signature
+                .s()
+                .to_vec_padded(P256_FIELD_LENGTH)
+                .map_err(AcmeError::OpenSsl)?,
+        );
+
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": base64url(&raw),
+        }))
+    }
+
+    fn coordinates(&self) -> Result<(Vec<u8>, Vec<u8>), AcmeError> {
+        let ec_key = self.key.ec_key().map_err(AcmeError::OpenSsl)?;
+        let mut context = BigNumContext::new().map_err(AcmeError::OpenSsl)?;
+        let mut x = BigNum::new().map_err(AcmeError::OpenSsl)?;
+        let mut y = BigNum::new().map_err(AcmeError::OpenSsl)?;
+        ec_key
+            .public_key()
+            .affine_coordinates(ec_key.group(), &mut x, &mut y, &mut context)
+            .map_err(AcmeError::OpenSsl)?;
+
+        Ok((
+            x.to_vec_padded(P256_FIELD_LENGTH)
+                .map_err(AcmeError::OpenSsl)?,
+            y.to_vec_padded(P256_FIELD_LENGTH)
+                .map_err(AcmeError::OpenSsl)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccountKey, base64url};
+    use crate::utils::testutil::TempDir;
+    use base64::Engine;
+    use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+    use openssl::bn::{BigNum, BigNumContext};
+    use openssl::ec::{EcGroup, EcKey, EcPoint};
+    use openssl::ecdsa::EcdsaSig;
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use openssl::sign::Verifier;
+    use serde_json::json;
+
+    fn decode(value: &serde_json::Value) -> Vec<u8> {
+        BASE64_URL_SAFE_NO_PAD
+            .decode(value.as_str().unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_sign() {
+        let key = AccountKey::generate().unwrap();
+        let jws = key
+            .sign(&json!({"alg": "ES256", "nonce": "abc"}), r#"{"a":1}"#)
+            .unwrap();
+
+        assert_eq!(decode(&jws["payload"]), br#"{"a":1}"#);
+        let signature = decode(&jws["signature"]);
+        assert_eq!(signature.len(), 64);
+
+        // verify with the public key rebuilt from the jwk
+        let jwk = key.jwk().unwrap();
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let mut context = BigNumContext::new().unwrap();
+        let mut encoded = vec![4];
+        encoded.extend(decode(&jwk["x"]));
+        encoded.extend(decode(&jwk["y"]));
+        let point = EcPoint::from_bytes(&group, &encoded, &mut context).unwrap();
+        let public_key =
+            PKey::from_ec_key(EcKey::from_public_key(&group, &point).unwrap()).unwrap();
+
+        let der = EcdsaSig::from_private_components(
+            BigNum::from_slice(&signature[..32]).unwrap(),
+            BigNum::from_slice(&signature[32..]).unwrap(),
+        )
+        .unwrap()
+        .to_der()
+        .unwrap();
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key).unwrap();
+        verifier
+            .update(
+                format!(
+                    "{}.{}",
+                    jws["protected"].as_str().unwrap(),
+                    jws["payload"].as_str().unwrap()
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        assert!(verifier.verify(&der).unwrap());
+    }
+
+    #[test]
+    fn test_load_or_generate() {
+        let root = TempDir::new("acme-key");
+        let path = root.join("account.pem");
+
+        let generated = AccountKey::load_or_generate(&path).unwrap();
+        let loaded = AccountKey::load_or_generate(&path).unwrap();
+        assert_eq!(
+            generated.thumbprint().unwrap(),
+            loaded.thumbprint().unwrap()
+        );
+        assert_eq!(
+            loaded.key_authorization("token").unwrap(),
+            format!("token.{}", loaded.thumbprint().unwrap())
+        );
+        assert_eq!(
+            loaded.thumbprint().unwrap().len(),
+            base64url(&[0; 32]).len()
+        );
+    }
+}
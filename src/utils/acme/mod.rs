@@ -0,0 +1,552 @@
+mod dns01;
+mod http01;
+mod jws;
+
+pub use self::dns01::{Dns01Solver, TsigKey};
+pub use self::http01::Http01Solver;
+pub use self::jws::AccountKey;
+
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::stack::Stack;
+use openssl::x509::X509ReqBuilder;
+use openssl::x509::extension::SubjectAlternativeName;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use url::Url;
+
+use self::jws::base64url;
+use crate::utils::configuration::AcmeKeyType;
+use crate::utils::errors::AcmeError;
+use crate::utils::http::{self, HttpResponse};
+use crate::utils::source::CertificatePair;
+
+/// Production directory of Let's Encrypt, used if `directory_url` is not set.
+pub const LETS_ENCRYPT_DIRECTORY_URL: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+/// Staging directory of Let's Encrypt, used by dry runs of jobs ordering from Let's Encrypt.
+pub const LETS_ENCRYPT_STAGING_DIRECTORY_URL: &str =
+    "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+/// Time between two polls of a pending authorization or order.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Number of polls before giving up on an authorization or order.
+const POLL_ATTEMPTS: u32 = 60;
+
+const BAD_NONCE: &str = "urn:ietf:params:acme:error:badNonce";
+
+/// Proves control over the identifiers of an order to the ACME server.
+pub trait ChallengeSolver {
+    /// Challenge type as named by the ACME server, e.g. `http-01`.
+    fn challenge_type(&self) -> &'static str;
+
+    /// Provides the response to the challenge with `token` for `identifier`.
+    fn present(
+        &mut self,
+        identifier: &str,
+        token: &str,
+        key_authorization: &str,
+    ) -> Result<(), AcmeError>;
+
+    /// Called once after all challenges of an order are presented, before the server is asked to
+    /// validate them.
+    fn ready(&mut self) -> Result<(), AcmeError> {
+        Ok(())
+    }
+
+    /// Removes the response provided by [`Self::present`].
+    fn cleanup(
+        &mut self,
+        identifier: &str,
+        token: &str,
+        key_authorization: &str,
+    ) -> Result<(), AcmeError>;
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Deserialize)]
+struct Order {
+    status: String,
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    status: String,
+    identifier: Identifier,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    #[serde(default)]
+    token: String,
+    error: Option<Problem>,
+}
+
+#[derive(Deserialize)]
+struct Problem {
+    #[serde(rename = "type", default)]
+    problem_type: String,
+    #[serde(default)]
+    detail: String,
+}
+
+/// A challenge presented by the solver, which has to be cleaned up after the order.
+struct PendingChallenge {
+    authorization_url: String,
+    challenge_url: String,
+    identifier: String,
+    token: String,
+    key_authorization: String,
+}
+
+/// Client for ordering certificates from an ACME (RFC 8555) server.
+pub struct AcmeClient {
+    directory: Directory,
+    ca_file: Option<String>,
+    connect_timeout: Duration,
+    key: AccountKey,
+    /// URL of the account, used as key id once registered.
+    account_url: Option<String>,
+    nonce: Option<String>,
+}
+
+impl AcmeClient {
+    /// Reads the directory at `directory_url`. The server certificate is verified against
+    /// `ca_file` if set, e.g. for a Pebble test server. Every connection fails after
+    /// `connect_timeout`.
+    pub fn connect(
+        directory_url: &str,
+        ca_file: Option<String>,
+        connect_timeout: Duration,
+        key: AccountKey,
+    ) -> Result<AcmeClient, AcmeError> {
+        let mut client = AcmeClient {
+            directory: Directory {
+                new_nonce: String::new(),
+                new_account: String::new(),
+                new_order: String::new(),
+            },
+            ca_file,
+            connect_timeout,
+            key,
+            account_url: None,
+            nonce: None,
+        };
+
+        let response = client.request(directory_url, "GET", &[], None)?;
+        if !response.is_success() {
+            return Err(problem(&response));
+        }
+        client.directory = parse(&response)?;
+        debug!(directory_url, "read acme directory");
+
+        Ok(client)
+    }
+
+    /// Registers the account of the key, or looks up the existing one, and agrees to the terms of
+    /// service of the server.
+    pub fn register(&mut self, contact: &[String]) -> Result<(), AcmeError> {
+        let url = self.directory.new_account.clone();
+        let response = self.post(
+            &url,
+            Some(&json!({"termsOfServiceAgreed": true, "contact": contact})),
+        )?;
+        let account_url = location(&response)?;
+        info!(account = %account_url, "using acme account");
+        self.account_url = Some(account_url);
+
+        Ok(())
+    }
+
+    /// Orders a certificate covering `hostnames`, proving control over them with `solver`, and
+    /// returns it with its newly generated private key.
+    pub fn order_certificate(
+        &mut self,
+        hostnames: &[String],
+        key_type: AcmeKeyType,
+        solver: &mut dyn ChallengeSolver,
+    ) -> Result<CertificatePair, AcmeError> {
+        let identifiers: Vec<Value> = hostnames
+            .iter()
+            .map(|hostname| json!({"type": "dns", "value": hostname}))
+            .collect();
+        let new_order = self.directory.new_order.clone();
+        let response = self.post(&new_order, Some(&json!({"identifiers": identifiers})))?;
+        let order_url = location(&response)?;
+        let order: Order = parse(&response)?;
+        info!(order = %order_url, hostnames = ?hostnames, "created acme order");
+
+        let mut pending = Vec::new();
+        let authorized = self.authorize(&order, solver, &mut pending);
+        for challenge in &pending {
+            if let Err(e) = solver.cleanup(
+                &challenge.identifier,
+                &challenge.token,
+                &challenge.key_authorization,
+            ) {
+                warn!(error = %e, identifier = challenge.identifier, "could not clean up challenge");
+            }
+        }
+        authorized?;
+
+        let private_key = generate_key(key_type).map_err(AcmeError::OpenSsl)?;
+        let csr = certificate_request(&private_key, hostnames).map_err(AcmeError::OpenSsl)?;
+        self.post(&order.finalize, Some(&json!({"csr": base64url(&csr)})))?;
+
+        let order: Order = self.poll(&order_url, "order", |order: &Order| {
+            match order.status.as_str() {
+                "valid" => Ok(true),
+                "pending" | "ready" | "processing" => Ok(false),
+                status => Err(AcmeError::OrderFailed(status.to_string())),
+            }
+        })?;
+        let certificate_url = order.certificate.ok_or(AcmeError::OrderFailed(
+            "valid without certificate".to_string(),
+        ))?;
+        let fullchain = self.post(&certificate_url, None)?.body;
+        info!(hostnames = ?hostnames, "acme order completed");
+
+        Ok(CertificatePair {
+            fullchain,
+            private_key: private_key
+                .private_key_to_pem_pkcs8()
+                .map_err(AcmeError::OpenSsl)?,
+        })
+    }
+
+    /// Presents and responds to the challenges of all pending authorizations of `order` and
+    /// waits until they are valid. Presented challenges are added to `pending`.
+    fn authorize(
+        &mut self,
+        order: &Order,
+        solver: &mut dyn ChallengeSolver,
+        pending: &mut Vec<PendingChallenge>,
+    ) -> Result<(), AcmeError> {
+        for authorization_url in &order.authorizations {
+            let authorization: Authorization = parse(&self.post(authorization_url, None)?)?;
+            if authorization.status == "valid" {
+                debug!(
+                    identifier = authorization.identifier.value,
+                    "already authorized"
+                );
+                continue;
+            }
+
+            let challenge = authorization
+                .challenges
+                .iter()
+                .find(|challenge| challenge.kind == solver.challenge_type())
+                .ok_or_else(|| AcmeError::ChallengeUnsupported {
+                    identifier: authorization.identifier.value.clone(),
+                    challenge: solver.challenge_type().to_string(),
+                })?;
+            let key_authorization = self.key.key_authorization(&challenge.token)?;
+            solver.present(
+                &authorization.identifier.value,
+                &challenge.token,
+                &key_authorization,
+            )?;
+            pending.push(PendingChallenge {
+                authorization_url: authorization_url.clone(),
+                challenge_url: challenge.url.clone(),
+                identifier: authorization.identifier.value.clone(),
+                token: challenge.token.clone(),
+                key_authorization,
+            });
+        }
+
+        if pending.is_empty() {
+            return Ok(());
+        }
+        solver.ready()?;
+
+        for challenge in pending.iter() {
+            self.post(&challenge.challenge_url, Some(&json!({})))?;
+        }
+        for challenge in pending.iter() {
+            self.poll(
+                &challenge.authorization_url,
+                "authorization",
+                |authorization: &Authorization| match authorization.status.as_str() {
+                    "valid" => Ok(true),
+                    "pending" => Ok(false),
+                    _ => Err(AcmeError::AuthorizationFailed {
+                        identifier: challenge.identifier.clone(),
+                        detail: authorization
+                            .challenges
+                            .iter()
+                            .find_map(|challenge| challenge.error.as_ref())
+                            .map(|error| format!("{} ({})", error.detail, error.problem_type))
+                            .unwrap_or_else(|| authorization.status.clone()),
+                    }),
+                },
+            )?;
+            info!(identifier = challenge.identifier, "authorization is valid");
+        }
+
+        Ok(())
+    }
+
+    /// Fetches `url` until `done` returns true for the response.
+    fn poll<T: DeserializeOwned>(
+        &mut self,
+        url: &str,
+        name: &str,
+        done: impl Fn(&T) -> Result<bool, AcmeError>,
+    ) -> Result<T, AcmeError> {
+        for _ in 0..POLL_ATTEMPTS {
+            let value: T = parse(&self.post(url, None)?)?;
+            if done(&value)? {
+                return Ok(value);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        Err(AcmeError::Timeout(format!("{name} {url}")))
+    }
+
+    /// Sends a signed POST request, or a POST-as-GET request if `payload` is `None`. Requests
+    /// rejected because of an outdated nonce are retried once with a fresh one.
+    fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<HttpResponse, AcmeError> {
+        let payload = payload.map(Value::to_string).unwrap_or_default();
+        let mut retried = false;
+
+        loop {
+            let mut protected = json!({
+                "alg": "ES256",
+                "nonce": self.nonce()?,
+                "url": url,
+            });
+            match &self.account_url {
+                Some(account_url) => protected["kid"] = json!(account_url),
+                None => protected["jwk"] = self.key.jwk()?,
+            }
+            let body = self.key.sign(&protected, &payload)?.to_string();
+
+            let response = self.request(
+                url,
+                "POST",
+                &[("Content-Type", "application/jose+json")],
+                Some(body.as_bytes()),
+            )?;
+            if response.is_success() {
+                return Ok(response);
+            }
+
+            let error = problem(&response);
+            match &error {
+                AcmeError::Problem { problem_type, .. }
+                    if problem_type == BAD_NONCE && !retried =>
+                {
+                    debug!(url, "retrying request with a fresh nonce");
+                    retried = true;
+                }
+                _ => return Err(error),
+            }
+        }
+    }
+
+    /// Returns the nonce of the last response, or requests a new one.
+    fn nonce(&mut self) -> Result<String, AcmeError> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+
+        let url = self.directory.new_nonce.clone();
+        self.request(&url, "HEAD", &[], None)?;
+        self.nonce
+            .take()
+            .ok_or(AcmeError::MissingHeader("Replay-Nonce"))
+    }
+
+    fn request(
+        &mut self,
+        url: &str,
+        method: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+    ) -> Result<HttpResponse, AcmeError> {
+        let url = Url::parse(url).map_err(|e| AcmeError::Connection(std::io::Error::other(e)))?;
+        let response = http::https_request(
+            &url,
+            method,
+            headers,
+            body,
+            self.ca_file.as_deref(),
+            self.connect_timeout,
+        )
+        .map_err(AcmeError::Connection)?;
+        if let Some(nonce) = response.header("Replay-Nonce") {
+            self.nonce = Some(nonce.to_string());
+        }
+
+        Ok(response)
+    }
+}
+
+/// Directory the account key for `directory_url` is stored in below `state_dir`, one per ACME
+/// server.
+pub fn account_key_path(state_dir: &Path, directory_url: &str) -> PathBuf {
+    let server = Url::parse(directory_url)
+        .ok()
+        .and_then(|url| {
+            url.host_str().map(|host| match url.port() {
+                Some(port) => format!("{host}_{port}"),
+                None => host.to_string(),
+            })
+        })
+        .unwrap_or_else(|| "default".to_string());
+
+    state_dir.join("acme").join(server).join("account.pem")
+}
+
+fn generate_key(key_type: AcmeKeyType) -> Result<PKey<Private>, openssl::error::ErrorStack> {
+    match key_type {
+        AcmeKeyType::Rsa2048 => PKey::from_rsa(Rsa::generate(2048)?),
+        AcmeKeyType::EcdsaP256 => {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+            PKey::from_ec_key(EcKey::generate(&group)?)
+        }
+    }
+}
+
+/// DER encoded certificate signing request for `hostnames`.
+fn certificate_request(
+    key: &PKey<Private>,
+    hostnames: &[String],
+) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+    let mut builder = X509ReqBuilder::new()?;
+    builder.set_pubkey(key)?;
+
+    let mut san = SubjectAlternativeName::new();
+    for hostname in hostnames {
+        san.dns(hostname);
+    }
+    let mut extensions = Stack::new()?;
+    extensions.push(san.build(&builder.x509v3_context(None))?)?;
+    builder.add_extensions(&extensions)?;
+    builder.sign(key, MessageDigest::sha256())?;
+
+    builder.build().to_der()
+}
+
+fn location(response: &HttpResponse) -> Result<String, AcmeError> {
+    response
+        .header("Location")
+        .map(str::to_string)
+        .ok_or(AcmeError::MissingHeader("Location"))
+}
+
+fn parse<T: DeserializeOwned>(response: &HttpResponse) -> Result<T, AcmeError> {
+    serde_json::from_slice(&response.body).map_err(AcmeError::Json)
+}
+
+/// Turns an error response into [`AcmeError::Problem`], using the RFC 7807 problem document if
+/// the server sent one.
+fn problem(response: &HttpResponse) -> AcmeError {
+    let problem: Problem = serde_json::from_slice(&response.body).unwrap_or(Problem {
+        problem_type: String::new(),
+        detail: String::from_utf8_lossy(&response.body).into_owned(),
+    });
+
+    AcmeError::Problem {
+        status: response.status,
+        problem_type: problem.problem_type,
+        detail: problem.detail,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AccountKey, AcmeClient, Http01Solver, account_key_path, certificate_request, generate_key,
+    };
+    use crate::utils::certvalidation::validate_certificate_pair;
+    use crate::utils::configuration::AcmeKeyType;
+    use openssl::x509::X509Req;
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[test]
+    fn test_account_key_path() {
+        let state_dir = Path::new("/var/lib/moorenew");
+        assert_eq!(
+            account_key_path(state_dir, super::LETS_ENCRYPT_DIRECTORY_URL),
+            state_dir.join("acme/acme-v02.api.letsencrypt.org/account.pem")
+        );
+        assert_eq!(
+            account_key_path(state_dir, "https://localhost:14000/dir"),
+            state_dir.join("acme/localhost_14000/account.pem")
+        );
+    }
+
+    #[test]
+    fn test_certificate_request() {
+        let key = generate_key(AcmeKeyType::EcdsaP256).unwrap();
+        let hostnames = vec!["mail.example.com".to_string(), "example.com".to_string()];
+        let request = X509Req::from_der(&certificate_request(&key, &hostnames).unwrap()).unwrap();
+
+        assert!(request.verify(&key).unwrap());
+        assert_eq!(request.extensions().unwrap().len(), 1);
+    }
+
+    /// Orders a certificate from a Pebble test server, e.g. started with
+    /// `PEBBLE_VA_ALWAYS_VALID=1 pebble -config test/config/pebble-config.json`. The directory and
+    /// the CA certificate of its API are read from `PEBBLE_DIRECTORY` and `PEBBLE_CA_FILE`, the
+    /// HTTP-01 responder listens on `PEBBLE_HTTP01_LISTEN` (default `0.0.0.0:5002`).
+    #[test]
+    #[ignore = "needs a running pebble server"]
+    fn test_pebble_order() {
+        let directory = std::env::var("PEBBLE_DIRECTORY")
+            .unwrap_or_else(|_| "https://localhost:14000/dir".to_string());
+        let ca_file = std::env::var("PEBBLE_CA_FILE").ok();
+        let listen =
+            std::env::var("PEBBLE_HTTP01_LISTEN").unwrap_or_else(|_| "0.0.0.0:5002".to_string());
+
+        let mut client = AcmeClient::connect(
+            &directory,
+            ca_file,
+            Duration::from_secs(10),
+            AccountKey::generate().unwrap(),
+        )
+        .unwrap();
+        client
+            .register(&["mailto:admin@example.com".to_string()])
+            .unwrap();
+
+        let hostnames = vec!["mail.example.com".to_string()];
+        let mut solver = Http01Solver::new(listen, None);
+        let pair = client
+            .order_certificate(&hostnames, AcmeKeyType::EcdsaP256, &mut solver)
+            .unwrap();
+
+        assert!(validate_certificate_pair(&pair.fullchain, &pair.private_key, &hostnames).is_ok());
+    }
+}
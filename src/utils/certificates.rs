@@ -1,20 +1,34 @@
-use crate::utils::errors::MoorenewError;
+use crate::utils::certvalidation::{CertificateInfo, validate_certificate_pair};
+use crate::utils::errors::{ConfigurationError, MoorenewError};
 use crate::utils::fileext::FileExt;
-use crate::utils::ssh::SSHClient;
-use std::fs::File;
-use std::path::Path;
-use std::process::exit;
-use tracing::info;
+use crate::utils::source::CertificateSource;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tracing::{debug, error, info, warn};
 
+/// Number of backups kept per job, older backups are removed after an installation.
+const BACKUP_RETENTION: usize = 10;
+
+/// File marking a domain directory in `mail_cert_path` as created by moorenew. Only marked
+/// directories are removed when their domain is removed from the configuration.
+const DOMAIN_MARKER: &str = ".moorenew";
+
+/// download_certificates fetches the certificate chain and private key from `source` if their
+/// checksums differ from the installed `cert.pem` and `key.pem`. Sources which can not calculate
+/// the checksums up front are fetched only once, for comparing and installing. The pair is
+/// validated before anything in `mail_cert_path` is written and then installed with
+/// [`install_certificates`]. Returns [`MoorenewError::NoChanges`] if the installed certificates
+/// are up to date or the fingerprint of the source certificate is one of `rolled_back`.
 pub fn download_certificates(
-    client: &SSHClient,
+    source: &dyn CertificateSource,
     mail_cert_path: &Path,
-    npm_cert_path: &Path,
+    hostnames: &[String],
+    backup_path: &Path,
+    rolled_back: &[String],
     dry_run: bool,
-) -> Result<(), MoorenewError> {
-    let npm_fullchain_path = npm_cert_path.join("fullchain.pem");
-    let npm_private_key_path = npm_cert_path.join("privkey.pem");
-
+) -> Result<CertificateInfo, MoorenewError> {
     let mailcow_cert_path = mail_cert_path.join("cert.pem");
     let mailcow_private_key_path = mail_cert_path.join("key.pem");
 
@@ -28,49 +42,430 @@ pub fn download_certificates(
         Err(_) => "".to_owned(),
     };
 
-    let mut downloads = 0;
-
     // Check via checksum if the certificates changed
+    let mut fetched = None;
     if !curr_cert_sha.is_empty() && !curr_private_key_sha.is_empty() {
-        if client.get_remote_sha256(&npm_fullchain_path)? != curr_cert_sha {
-            info!("downloaded fullchain.pem into cert.pem");
-            if !dry_run {
-                client
-                    .download_file(&npm_fullchain_path, &mailcow_cert_path)
-                    .map_err(MoorenewError::FileTransfer)?;
-            }
-            downloads += 1;
+        let checksums = match source.checksums()? {
+            Some(checksums) => checksums,
+            None => fetched.insert(source.fetch()?).checksums(),
+        };
+        if checksums == (curr_cert_sha, curr_private_key_sha) {
+            info!(source = %source.location(), "no new certificates available");
+            return Err(MoorenewError::NoChanges);
         }
+    }
 
-        if client.get_remote_sha256(&npm_private_key_path)? != curr_private_key_sha {
-            info!("downloaded privkey.pem into key.pem");
-            if !dry_run {
-                client
-                    .download_file(&npm_private_key_path, &mailcow_private_key_path)
-                    .map_err(MoorenewError::FileTransfer)?;
-            }
-            downloads += 1;
-        }
+    let pair = match fetched {
+        Some(pair) => pair,
+        None => source.fetch()?,
+    };
+
+    let certificate_info =
+        validate_certificate_pair(&pair.fullchain, &pair.private_key, hostnames)?;
+    if rolled_back.contains(&certificate_info.fingerprint) {
+        warn!(source = %source.location(), fingerprint = %certificate_info.fingerprint, "the certificate was rolled back, not installing it again");
+        return Err(MoorenewError::NoChanges);
+    }
+
+    if dry_run {
+        info!("dry run, not installing the certificates");
     } else {
-        if !dry_run {
-            client
-                .download_file(&npm_fullchain_path, &mailcow_cert_path)
-                .map_err(MoorenewError::FileTransfer)?;
-
-            client
-                .download_file(&npm_private_key_path, &mailcow_private_key_path)
-                .map_err(MoorenewError::FileTransfer)?;
+        install_certificates(
+            mail_cert_path,
+            &pair.fullchain,
+            &pair.private_key,
+            backup_path,
+        )?;
+        info!(source = %source.location(), "installed the certificates as cert.pem and key.pem");
+    }
+
+    Ok(certificate_info)
+}
+
+/// install_certificates replaces `cert.pem` and `key.pem` in `mail_cert_path`. Both files are
+/// written to temporary files next to their destination first and verified, so a failure leaves
+/// the installed pair untouched. The previous pair is copied to a timestamped directory in
+/// `backup_path` before the temporary files are renamed into place.
+pub fn install_certificates(
+    mail_cert_path: &Path,
+    certificate: &[u8],
+    private_key: &[u8],
+    backup_path: &Path,
+) -> Result<(), MoorenewError> {
+    let cert_path = mail_cert_path.join("cert.pem");
+    let key_path = mail_cert_path.join("key.pem");
+
+    let (temp_cert_path, temp_key_path) =
+        write_temp_pair(&cert_path, &key_path, certificate, private_key)?;
+
+    if let Err(e) = backup_certificates(mail_cert_path, backup_path) {
+        let _ = fs::remove_file(&temp_cert_path);
+        let _ = fs::remove_file(&temp_key_path);
+        return Err(e);
+    }
+
+    rename_pair(&cert_path, &key_path, &temp_cert_path, &temp_key_path)?;
+
+    // persist the renames, failing here does not affect the installed files
+    if let Err(e) = File::open(mail_cert_path).and_then(|dir| dir.sync_all()) {
+        warn!(error = %e, "could not sync certificate directory");
+    }
+
+    Ok(())
+}
+
+/// rollback_certificates installs the most recent backup of `backup_path` and removes it from
+/// the backups afterwards, so consecutive rollbacks step back through older pairs.
+pub fn rollback_certificates(
+    mail_cert_path: &Path,
+    backup_path: &Path,
+) -> Result<PathBuf, MoorenewError> {
+    let backup = list_backups(backup_path)?
+        .pop()
+        .ok_or(MoorenewError::NoBackupAvailable)?;
+
+    let certificate =
+        fs::read(backup.join("cert.pem")).map_err(MoorenewError::CertificateBackup)?;
+    let private_key = fs::read(backup.join("key.pem")).map_err(MoorenewError::CertificateBackup)?;
+
+    let cert_path = mail_cert_path.join("cert.pem");
+    let key_path = mail_cert_path.join("key.pem");
+    let (temp_cert_path, temp_key_path) =
+        write_temp_pair(&cert_path, &key_path, &certificate, &private_key)?;
+    rename_pair(&cert_path, &key_path, &temp_cert_path, &temp_key_path)?;
+
+    fs::remove_dir_all(&backup).map_err(MoorenewError::CertificateBackup)?;
+    info!(backup = %backup.display(), "restored certificates from backup");
+
+    Ok(backup)
+}
+
+/// Returns the SNI directory of `domain` in `mail_cert_path`. Unless `dry_run` is set, the
+/// directory and its [`DOMAIN_MARKER`] are created if missing.
+pub fn domain_cert_path(
+    mail_cert_path: &Path,
+    domain: &str,
+    dry_run: bool,
+) -> Result<PathBuf, MoorenewError> {
+    if domain.is_empty()
+        || domain == "."
+        || domain == ".."
+        || domain.contains(['/', '\\'])
+        || domain.starts_with('.')
+    {
+        return Err(MoorenewError::ConfigurationError(
+            ConfigurationError::InvalidDomain(domain.to_string()),
+        ));
+    }
+
+    let domain_path = mail_cert_path.join(domain);
+    if dry_run {
+        return Ok(domain_path);
+    }
+
+    let marker = domain_path.join(DOMAIN_MARKER);
+    if !marker.exists() {
+        fs::create_dir_all(&domain_path).map_err(MoorenewError::CertificateInstallation)?;
+        File::create(&marker).map_err(MoorenewError::CertificateInstallation)?;
+        debug!(directory = %domain_path.display(), "created domain directory");
+    }
+
+    Ok(domain_path)
+}
+
+/// remove_stale_domains removes the domain directories moorenew created in `mail_cert_path` whose
+/// domain is not in `domains` anymore and returns the removed domains. Directories without the
+/// [`DOMAIN_MARKER`] are never touched. With `dry_run` nothing is removed.
+pub fn remove_stale_domains(
+    mail_cert_path: &Path,
+    domains: &[&str],
+    dry_run: bool,
+) -> Result<Vec<String>, MoorenewError> {
+    if !mail_cert_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut stale_domains: Vec<String> = fs::read_dir(mail_cert_path)
+        .map_err(MoorenewError::CertificateInstallation)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().join(DOMAIN_MARKER).is_file())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|domain| !domains.contains(&domain.as_str()))
+        .collect();
+    stale_domains.sort();
+
+    for domain in &stale_domains {
+        if dry_run {
+            info!(
+                domain,
+                "dry run, not removing the certificates of removed domain"
+            );
+            continue;
         }
-        downloads += 2
+        fs::remove_dir_all(mail_cert_path.join(domain))
+            .map_err(MoorenewError::CertificateInstallation)?;
+        info!(domain, "removed the certificates of removed domain");
     }
 
-    if downloads == 0 {
-        info!("no new certificates available, exiting");
-        tracing::Span::current().record("result", "up to date");
-        exit(0)
-    } else {
-        info!("downloaded {} certificates", downloads);
+    Ok(stale_domains)
+}
+
+/// Writes the certificate and the private key to temporary files next to their destinations. If
+/// the key can not be written, the temporary certificate is removed again.
+fn write_temp_pair(
+    cert_path: &Path,
+    key_path: &Path,
+    certificate: &[u8],
+    private_key: &[u8],
+) -> Result<(PathBuf, PathBuf), MoorenewError> {
+    let temp_cert_path = write_temp_file(cert_path, certificate, 0o644)?;
+    match write_temp_file(key_path, private_key, 0o600) {
+        Ok(temp_key_path) => Ok((temp_cert_path, temp_key_path)),
+        Err(e) => {
+            let _ = fs::remove_file(&temp_cert_path);
+            Err(e)
+        }
+    }
+}
+
+/// Renames the temporary files of a pair into place. If the key can not be renamed after the
+/// certificate was, the previous `cert.pem` is restored, so the directory never ends up with a
+/// certificate which does not belong to its key.
+fn rename_pair(
+    cert_path: &Path,
+    key_path: &Path,
+    temp_cert_path: &Path,
+    temp_key_path: &Path,
+) -> Result<(), MoorenewError> {
+    let remove_temp_files = || {
+        let _ = fs::remove_file(temp_cert_path);
+        let _ = fs::remove_file(temp_key_path);
+    };
+
+    let previous_certificate = match fs::read(cert_path) {
+        Ok(certificate) => Some(certificate),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            remove_temp_files();
+            return Err(MoorenewError::CertificateInstallation(e));
+        }
+    };
+
+    if let Err(e) = fs::rename(temp_cert_path, cert_path) {
+        remove_temp_files();
+        return Err(MoorenewError::CertificateInstallation(e));
+    }
+    if let Err(e) = fs::rename(temp_key_path, key_path) {
+        remove_temp_files();
+        let restored = match previous_certificate {
+            Some(certificate) => write_temp_file(cert_path, &certificate, 0o644).and_then(|temp| {
+                fs::rename(&temp, cert_path).map_err(|e| {
+                    let _ = fs::remove_file(&temp);
+                    MoorenewError::CertificateInstallation(e)
+                })
+            }),
+            None => fs::remove_file(cert_path).map_err(MoorenewError::CertificateInstallation),
+        };
+        if let Err(restore_error) = restored {
+            error!(error = %restore_error, file = %cert_path.display(), "could not restore the previous certificate, it does not match the installed key");
+        }
+        return Err(MoorenewError::CertificateInstallation(e));
     }
 
     Ok(())
 }
+
+/// Writes `contents` to a hidden temporary file next to `destination` and reads it back to verify
+/// it. The permissions of an existing `destination` are kept, `mode` is used otherwise.
+fn write_temp_file(
+    destination: &Path,
+    contents: &[u8],
+    mode: u32,
+) -> Result<PathBuf, MoorenewError> {
+    let file_name = destination
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let temp_path = destination.with_file_name(format!(".{file_name}.moorenew-tmp"));
+
+    let permissions = match fs::metadata(destination) {
+        Ok(metadata) => metadata.permissions(),
+        Err(_) => fs::Permissions::from_mode(mode),
+    };
+
+    let write = || -> std::io::Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(mode)
+            .open(&temp_path)?;
+        file.set_permissions(permissions)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+
+        if fs::read(&temp_path)? != contents {
+            return Err(std::io::Error::other(
+                "temporary file does not match the downloaded file",
+            ));
+        }
+        Ok(())
+    };
+
+    write().map_err(|e| {
+        let _ = fs::remove_file(&temp_path);
+        MoorenewError::CertificateInstallation(e)
+    })?;
+
+    debug!(file = %temp_path.display(), "wrote temporary file");
+    Ok(temp_path)
+}
+
+fn backup_certificates(mail_cert_path: &Path, backup_path: &Path) -> Result<(), MoorenewError> {
+    let cert_path = mail_cert_path.join("cert.pem");
+    let key_path = mail_cert_path.join("key.pem");
+
+    if !cert_path.exists() || !key_path.exists() {
+        debug!("no installed certificates to back up");
+        return Ok(());
+    }
+
+    // with microseconds, so backups of consecutive installations never share a directory
+    let backup = backup_path.join(chrono::Utc::now().format("%Y%m%dT%H%M%S%.6fZ").to_string());
+    fs::create_dir_all(&backup).map_err(MoorenewError::CertificateBackup)?;
+    fs::set_permissions(&backup, fs::Permissions::from_mode(0o700))
+        .map_err(MoorenewError::CertificateBackup)?;
+    fs::copy(&cert_path, backup.join("cert.pem")).map_err(MoorenewError::CertificateBackup)?;
+    fs::copy(&key_path, backup.join("key.pem")).map_err(MoorenewError::CertificateBackup)?;
+    info!(backup = %backup.display(), "backed up installed certificates");
+
+    let backups = list_backups(backup_path)?;
+    if backups.len() > BACKUP_RETENTION {
+        for old_backup in &backups[..backups.len() - BACKUP_RETENTION] {
+            if let Err(e) = fs::remove_dir_all(old_backup) {
+                warn!(error = %e, backup = %old_backup.display(), "could not remove old backup");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the backups in `backup_path` ordered from oldest to newest.
+fn list_backups(backup_path: &Path) -> Result<Vec<PathBuf>, MoorenewError> {
+    if !backup_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut backups: Vec<PathBuf> = fs::read_dir(backup_path)
+        .map_err(MoorenewError::CertificateBackup)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.join("cert.pem").is_file() && path.join("key.pem").is_file())
+        .collect();
+    backups.sort();
+
+    Ok(backups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        domain_cert_path, install_certificates, remove_stale_domains, rename_pair,
+        rollback_certificates, write_temp_file,
+    };
+    use crate::utils::testutil::TempDir;
+    use std::fs;
+
+    #[test]
+    fn test_install_and_rollback() {
+        let root = TempDir::new("install");
+        let mail_cert_path = root.join("ssl");
+        let backup_path = root.join("backups");
+        fs::create_dir_all(&mail_cert_path).unwrap();
+
+        install_certificates(&mail_cert_path, b"cert 1", b"key 1", &backup_path).unwrap();
+        assert!(!backup_path.exists());
+
+        install_certificates(&mail_cert_path, b"cert 2", b"key 2", &backup_path).unwrap();
+        assert_eq!(
+            fs::read(mail_cert_path.join("cert.pem")).unwrap(),
+            b"cert 2"
+        );
+        assert_eq!(fs::read(mail_cert_path.join("key.pem")).unwrap(), b"key 2");
+        assert_eq!(fs::read_dir(&mail_cert_path).unwrap().count(), 2);
+
+        rollback_certificates(&mail_cert_path, &backup_path).unwrap();
+        assert_eq!(
+            fs::read(mail_cert_path.join("cert.pem")).unwrap(),
+            b"cert 1"
+        );
+        assert_eq!(fs::read(mail_cert_path.join("key.pem")).unwrap(), b"key 1");
+        assert!(rollback_certificates(&mail_cert_path, &backup_path).is_err());
+
+        // installations within the same second get a backup each
+        for certificate in [b"cert 3", b"cert 4", b"cert 5"] {
+            install_certificates(&mail_cert_path, certificate, b"key", &backup_path).unwrap();
+        }
+        assert_eq!(fs::read_dir(&backup_path).unwrap().count(), 3);
+        rollback_certificates(&mail_cert_path, &backup_path).unwrap();
+        assert_eq!(
+            fs::read(mail_cert_path.join("cert.pem")).unwrap(),
+            b"cert 4"
+        );
+    }
+
+    #[test]
+    fn test_rename_pair_restores_certificate() {
+        let root = TempDir::new("rename");
+        let cert_path = root.join("cert.pem");
+        let key_path = root.join("key.pem");
+        fs::write(&cert_path, b"cert 1").unwrap();
+        fs::write(&key_path, b"key 1").unwrap();
+
+        // the temporary key is missing, so renaming it fails after the certificate was renamed
+        let temp_cert_path = write_temp_file(&cert_path, b"cert 2", 0o644).unwrap();
+        assert!(
+            rename_pair(
+                &cert_path,
+                &key_path,
+                &temp_cert_path,
+                &root.join(".key.pem.moorenew-tmp")
+            )
+            .is_err()
+        );
+        assert_eq!(fs::read(&cert_path).unwrap(), b"cert 1");
+        assert_eq!(fs::read(&key_path).unwrap(), b"key 1");
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_domain_directories() {
+        let root = TempDir::new("domains");
+        fs::create_dir_all(root.join("manual")).unwrap();
+
+        assert!(domain_cert_path(&root, "../etc", false).is_err());
+        assert!(
+            !domain_cert_path(&root, "example.org", true)
+                .unwrap()
+                .exists()
+        );
+        for domain in ["example.org", "example.net"] {
+            let domain_path = domain_cert_path(&root, domain, false).unwrap();
+            assert!(domain_path.join(".moorenew").is_file());
+        }
+
+        assert_eq!(
+            remove_stale_domains(&root, &["example.org"], true).unwrap(),
+            vec!["example.net"]
+        );
+        assert!(root.join("example.net").exists());
+
+        assert_eq!(
+            remove_stale_domains(&root, &["example.org"], false).unwrap(),
+            vec!["example.net"]
+        );
+        assert!(!root.join("example.net").exists());
+        assert!(root.join("example.org").exists());
+        assert!(root.join("manual").exists());
+    }
+}
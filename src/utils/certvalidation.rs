@@ -0,0 +1,345 @@
+use openssl::asn1::{Asn1Time, Asn1TimeRef};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::x509::{X509, X509NameRef, X509Ref, X509VerifyResult};
+use std::cmp::Ordering;
+use tracing::{debug, info};
+
+use crate::utils::errors::{CertificateValidationError, MoorenewError};
+
+/// Details about the leaf certificate of a certificate chain.
+#[derive(Debug, Clone)]
+pub struct CertificateInfo {
+    /// Lowercase hex encoded SHA-256 fingerprint of the DER encoded certificate.
+    pub fingerprint: String,
+    /// Lowercase hex encoded serial number.
+    pub serial: String,
+    pub subject: String,
+    pub not_before: String,
+    pub not_after: String,
+    pub dns_names: Vec<String>,
+}
+
+impl CertificateInfo {
+    fn from_x509(certificate: &X509Ref) -> Result<CertificateInfo, MoorenewError> {
+        let fingerprint = certificate
+            .digest(MessageDigest::sha256())
+            .map_err(|e| validation_error(CertificateValidationError::OpenSsl(e)))?;
+        let serial = certificate
+            .serial_number()
+            .to_bn()
+            .and_then(|serial| serial.to_hex_str().map(|hex| hex.to_lowercase()))
+            .map_err(|e| validation_error(CertificateValidationError::OpenSsl(e)))?;
+
+        Ok(CertificateInfo {
+            fingerprint: fingerprint.iter().map(|b| format!("{:02x}", b)).collect(),
+            serial,
+            subject: format_name(certificate.subject_name()),
+            not_before: certificate.not_before().to_string(),
+            not_after: certificate.not_after().to_string(),
+            dns_names: dns_names(certificate),
+        })
+    }
+}
+
+/// Validates a PEM encoded certificate chain and private key before they are installed. The
+/// following checks are performed:
+/// - the private key matches the public key of the leaf certificate
+/// - every certificate in the chain is issued by the certificate following it and the chain does
+///   not end with a leaf certificate missing its intermediates
+/// - the leaf certificate is currently valid
+/// - the leaf certificate covers all `hostnames`, taking wildcard names into account
+pub fn validate_certificate_pair(
+    fullchain: &[u8],
+    private_key: &[u8],
+    hostnames: &[String],
+) -> Result<CertificateInfo, MoorenewError> {
+    let chain = parse_chain(fullchain)?;
+    let leaf = chain
+        .first()
+        .ok_or(validation_error(CertificateValidationError::EmptyChain))?;
+
+    let private_key = PKey::private_key_from_pem(private_key)
+        .map_err(|e| validation_error(CertificateValidationError::PrivateKeyParsing(e)))?;
+    let public_key = leaf
+        .public_key()
+        .map_err(|e| validation_error(CertificateValidationError::OpenSsl(e)))?;
+    if !public_key.public_eq(&private_key) {
+        return Err(validation_error(CertificateValidationError::KeyMismatch));
+    }
+    debug!("private key matches the leaf certificate");
+
+    check_chain(&chain)?;
+    debug!(
+        certificates = chain.len(),
+        "certificate chain is ordered and complete"
+    );
+
+    check_validity(leaf.not_before(), leaf.not_after())?;
+
+    let info = CertificateInfo::from_x509(leaf)?;
+    let uncovered: Vec<String> = hostnames
+        .iter()
+        .filter(|hostname| {
+            !info
+                .dns_names
+                .iter()
+                .any(|name| hostname_matches(name, hostname))
+        })
+        .cloned()
+        .collect();
+    if !uncovered.is_empty() {
+        return Err(validation_error(
+            CertificateValidationError::HostnamesNotCovered {
+                hostnames: uncovered,
+            },
+        ));
+    }
+
+    info!(subject = %info.subject, not_after = %info.not_after, fingerprint = %info.fingerprint, "certificate passed validation");
+    Ok(info)
+}
+
+/// issued_at returns the `notBefore` time of the leaf certificate of a PEM encoded chain as a unix
+/// timestamp, or `None` if the leaf is not currently valid or does not cover `hostname`.
+pub fn issued_at(fullchain: &[u8], hostname: &str) -> Result<Option<i64>, MoorenewError> {
+    let chain = parse_chain(fullchain)?;
+    let leaf = &chain[0];
+
+    if check_validity(leaf.not_before(), leaf.not_after()).is_err()
+        || !dns_names(leaf)
+            .iter()
+            .any(|name| hostname_matches(name, hostname))
+    {
+        return Ok(None);
+    }
+
+    let diff = Asn1Time::from_unix(0)
+        .and_then(|epoch| epoch.diff(leaf.not_before()))
+        .map_err(|e| validation_error(CertificateValidationError::OpenSsl(e)))?;
+    Ok(Some(i64::from(diff.days) * 86_400 + i64::from(diff.secs)))
+}
+
+/// Details about the leaf certificate of a PEM encoded chain, without validating it.
+pub fn leaf_certificate(fullchain: &[u8]) -> Result<CertificateInfo, MoorenewError> {
+    CertificateInfo::from_x509(&parse_chain(fullchain)?[0])
+}
+
+/// Whole days until the leaf certificate of a PEM encoded chain expires, negative if it already
+/// expired.
+pub fn days_until_expiry(fullchain: &[u8]) -> Result<i32, MoorenewError> {
+    let chain = parse_chain(fullchain)?;
+    let diff = Asn1Time::days_from_now(0)
+        .and_then(|now| now.diff(chain[0].not_after()))
+        .map_err(|e| validation_error(CertificateValidationError::OpenSsl(e)))?;
+
+    Ok(diff.days)
+}
+
+/// Whether the leaf certificate of a PEM encoded chain covers all `hostnames`.
+pub fn covers_hostnames(fullchain: &[u8], hostnames: &[String]) -> Result<bool, MoorenewError> {
+    let chain = parse_chain(fullchain)?;
+    let dns_names = dns_names(&chain[0]);
+
+    Ok(hostnames.iter().all(|hostname| {
+        dns_names
+            .iter()
+            .any(|name| hostname_matches(name, hostname))
+    }))
+}
+
+fn validation_error(error: CertificateValidationError) -> MoorenewError {
+    MoorenewError::CertificateValidation(error)
+}
+
+fn parse_chain(pem: &[u8]) -> Result<Vec<X509>, MoorenewError> {
+    let chain = X509::stack_from_pem(pem)
+        .map_err(|e| validation_error(CertificateValidationError::ChainParsing(e)))?;
+    if chain.is_empty() {
+        return Err(validation_error(CertificateValidationError::EmptyChain));
+    }
+    Ok(chain)
+}
+
+fn check_chain(chain: &[X509]) -> Result<(), MoorenewError> {
+    for (index, pair) in chain.windows(2).enumerate() {
+        let (subject, issuer) = (&pair[0], &pair[1]);
+        let issuer_key = issuer
+            .public_key()
+            .map_err(|e| validation_error(CertificateValidationError::OpenSsl(e)))?;
+        let signed = subject
+            .verify(&issuer_key)
+            .map_err(|e| validation_error(CertificateValidationError::OpenSsl(e)))?;
+        if issuer.issued(subject) != X509VerifyResult::OK || !signed {
+            return Err(validation_error(CertificateValidationError::ChainOrder {
+                index,
+            }));
+        }
+    }
+
+    // The root certificate is usually not part of the chain, so the chain is only known to be
+    // incomplete if it consists of a single certificate which is not self-signed
+    if let [leaf] = chain
+        && leaf.issued(leaf) != X509VerifyResult::OK
+    {
+        return Err(validation_error(
+            CertificateValidationError::ChainIncomplete {
+                subject: format_name(leaf.subject_name()),
+            },
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_validity(not_before: &Asn1TimeRef, not_after: &Asn1TimeRef) -> Result<(), MoorenewError> {
+    let now = Asn1Time::days_from_now(0)
+        .map_err(|e| validation_error(CertificateValidationError::OpenSsl(e)))?;
+
+    if not_before
+        .compare(&now)
+        .map_err(|e| validation_error(CertificateValidationError::OpenSsl(e)))?
+        == Ordering::Greater
+    {
+        return Err(validation_error(CertificateValidationError::NotYetValid {
+            not_before: not_before.to_string(),
+        }));
+    }
+
+    if not_after
+        .compare(&now)
+        .map_err(|e| validation_error(CertificateValidationError::OpenSsl(e)))?
+        != Ordering::Greater
+    {
+        return Err(validation_error(CertificateValidationError::Expired {
+            not_after: not_after.to_string(),
+        }));
+    }
+
+    Ok(())
+}
+
+fn dns_names(certificate: &X509Ref) -> Vec<String> {
+    certificate
+        .subject_alt_names()
+        .map(|names| {
+            names
+                .iter()
+                .filter_map(|name| name.dnsname().map(str::to_lowercase))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn format_name(name: &X509NameRef) -> String {
+    name.entries()
+        .map(|entry| {
+            let key = entry.object().nid().short_name().unwrap_or("?");
+            format!("{key}={}", String::from_utf8_lossy(entry.data().as_slice()))
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Checks if a DNS name of a certificate covers the hostname. A wildcard only matches a single
+/// label, so `*.example.com` covers `mail.example.com` but neither `example.com` nor
+/// `a.mail.example.com`.
+pub fn hostname_matches(dns_name: &str, hostname: &str) -> bool {
+    let dns_name = dns_name.trim_end_matches('.').to_lowercase();
+    let hostname = hostname.trim_end_matches('.').to_lowercase();
+
+    match dns_name.strip_prefix("*.") {
+        Some(domain) => hostname
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest == domain),
+        None => dns_name == hostname,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hostname_matches, validate_certificate_pair};
+    use crate::utils::errors::{CertificateValidationError, MoorenewError};
+    use crate::utils::testutil::{certificate, key};
+    use openssl::x509::X509;
+
+    fn pem_chain(certificates: &[&X509]) -> Vec<u8> {
+        certificates
+            .iter()
+            .flat_map(|certificate| certificate.to_pem().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_certificate_pair() {
+        let ca_key = key();
+        let ca = certificate("Moorenew Test CA", &ca_key, None, 0, 3650);
+        let leaf_key = key();
+        let leaf = certificate("mail.example.com", &leaf_key, Some((&ca, &ca_key)), 0, 90);
+        let expired = certificate("mail.example.com", &leaf_key, Some((&ca, &ca_key)), 0, 0);
+        let private_key = leaf_key.private_key_to_pem_pkcs8().unwrap();
+        let hostnames = vec!["mail.example.com".to_string()];
+
+        let info =
+            validate_certificate_pair(&pem_chain(&[&leaf, &ca]), &private_key, &hostnames).unwrap();
+        assert_eq!(info.dns_names, vec!["mail.example.com"]);
+
+        assert!(matches!(
+            validate_certificate_pair(
+                &pem_chain(&[&leaf, &ca]),
+                &key().private_key_to_pem_pkcs8().unwrap(),
+                &hostnames
+            ),
+            Err(MoorenewError::CertificateValidation(
+                CertificateValidationError::KeyMismatch
+            ))
+        ));
+        let other_ca_key = key();
+        let other_ca = certificate("Other Test CA", &other_ca_key, None, 0, 3650);
+        assert!(matches!(
+            validate_certificate_pair(&pem_chain(&[&leaf, &other_ca]), &private_key, &hostnames),
+            Err(MoorenewError::CertificateValidation(
+                CertificateValidationError::ChainOrder { index: 0 }
+            ))
+        ));
+        assert!(matches!(
+            validate_certificate_pair(&pem_chain(&[&leaf]), &private_key, &hostnames),
+            Err(MoorenewError::CertificateValidation(
+                CertificateValidationError::ChainIncomplete { .. }
+            ))
+        ));
+        assert!(matches!(
+            validate_certificate_pair(&pem_chain(&[&expired, &ca]), &private_key, &hostnames),
+            Err(MoorenewError::CertificateValidation(
+                CertificateValidationError::Expired { .. }
+            ))
+        ));
+        assert!(matches!(
+            validate_certificate_pair(
+                &pem_chain(&[&leaf, &ca]),
+                &private_key,
+                &["imap.example.com".to_string()]
+            ),
+            Err(MoorenewError::CertificateValidation(
+                CertificateValidationError::HostnamesNotCovered { .. }
+            ))
+        ));
+        assert!(matches!(
+            validate_certificate_pair(
+                b"-----BEGIN CERTIFICATE-----\nMIIB",
+                &private_key,
+                &hostnames
+            ),
+            Err(MoorenewError::CertificateValidation(_))
+        ));
+    }
+
+    #[test]
+    fn test_hostname_matches() {
+        assert!(hostname_matches("mail.example.com", "MAIL.example.com"));
+        assert!(hostname_matches("*.example.com", "mail.example.com"));
+        assert!(!hostname_matches("*.example.com", "example.com"));
+        assert!(!hostname_matches("*.example.com", "a.mail.example.com"));
+        assert!(!hostname_matches("mail.example.com", "smtp.example.com"));
+    }
+}
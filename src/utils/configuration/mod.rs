@@ -1,4 +1,7 @@
 #[allow(clippy::module_inception)]
 mod configuration;
+mod overrides;
+mod validation;
 
 pub use self::configuration::*;
+pub use self::validation::{ValidationProblem, validate_config, validate_configuration};
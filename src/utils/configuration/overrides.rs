@@ -0,0 +1,311 @@
+use std::path::Path;
+use toml::{Table, Value};
+
+use super::configuration::LEGACY_JOB_KEYS;
+use crate::utils::errors::{ConfigurationError, MoorenewError};
+
+/// Prefix of environment variables overriding configuration fields.
+const ENV_PREFIX: &str = "MOORENEW_";
+
+/// Environment variables with the prefix which are not configuration fields.
+const RESERVED_VARIABLES: &[&str] = &["MOORENEW_CONFIG"];
+
+/// Fields holding a list, which are read line by line from a secret file.
+const LIST_FIELDS: &[&str] = &["buzz_urls", "hostnames"];
+
+/// Suffix of keys reading the value of the field without the suffix from a file.
+const FILE_SUFFIX: &str = "_file";
+
+/// Fields ending with the suffix which are paths themselves and not read as secrets.
+const PATH_FIELDS: &[&str] = &["ca_file"];
+
+/// apply_env_overrides sets configuration fields from `MOORENEW_*` variables. The rest of the
+/// variable name is the lowercased path of the field, with `__` separating nested tables and
+/// array indices, e.g. `MOORENEW_LOGGING__LOKI__PASSWORD` or `MOORENEW_JOBS__0__SFTP_HOST`.
+///
+/// Values are parsed as TOML values, so `22`, `true` and `["a", "b"]` keep their type, and are used
+/// as plain strings otherwise. If the field is a string in the configuration file, the value is
+/// always used as a string.
+///
+/// A legacy single job configured through top level fields is only moved into `jobs` after the
+/// overrides, so `jobs` cannot be overridden by index then and its fields are overridden without
+/// the `JOBS__<n>__` prefix.
+pub(super) fn apply_env_overrides(
+    configuration: &mut Table,
+    variables: impl IntoIterator<Item = (String, String)>,
+) -> Result<(), MoorenewError> {
+    let mut variables: Vec<(String, String)> = variables
+        .into_iter()
+        .filter(|(name, _)| {
+            name.starts_with(ENV_PREFIX) && !RESERVED_VARIABLES.contains(&name.as_str())
+        })
+        .collect();
+    // apply shorter paths first, so `MOORENEW_JOBS` can be refined by `MOORENEW_JOBS__0__NAME`
+    variables.sort();
+    let legacy_job = LEGACY_JOB_KEYS
+        .iter()
+        .any(|key| configuration.contains_key(*key));
+
+    for (name, value) in variables {
+        let path: Vec<String> = name[ENV_PREFIX.len()..]
+            .split("__")
+            .map(str::to_lowercase)
+            .collect();
+        if path.iter().any(String::is_empty) {
+            return Err(invalid_override(&name, "empty path segment"));
+        }
+        if legacy_job && path[0] == "jobs" {
+            return Err(invalid_override(
+                &name,
+                "the job is configured through top level fields, override them without `JOBS__<n>__`",
+            ));
+        }
+
+        set_path(configuration, &path, &value)
+            .map_err(|reason| invalid_override(&name, &reason))?;
+    }
+
+    Ok(())
+}
+
+/// resolve_secret_files replaces every `<field>_file` key with `<field>`, set to the contents of
+/// the referenced file without the trailing newline. List fields like `buzz_urls` get one entry
+/// per non-empty line.
+pub(super) fn resolve_secret_files(table: &mut Table) -> Result<(), MoorenewError> {
+    let file_keys: Vec<String> = table
+        .keys()
+        .filter(|key| {
+            key.len() > FILE_SUFFIX.len()
+                && key.ends_with(FILE_SUFFIX)
+                && !PATH_FIELDS.contains(&key.as_str())
+        })
+        .cloned()
+        .collect();
+
+    for file_key in file_keys {
+        let field = file_key[..file_key.len() - FILE_SUFFIX.len()].to_string();
+        let Some(Value::String(path)) = table.remove(&file_key) else {
+            return Err(MoorenewError::ConfigurationError(
+                ConfigurationError::InvalidOverride {
+                    variable: file_key,
+                    reason: "expected a file path".to_string(),
+                },
+            ));
+        };
+
+        let contents = std::fs::read_to_string(Path::new(&path)).map_err(|e| {
+            MoorenewError::ConfigurationError(ConfigurationError::SecretFile {
+                key: file_key.clone(),
+                path: path.clone(),
+                error: e,
+            })
+        })?;
+
+        let value = if LIST_FIELDS.contains(&field.as_str()) {
+            Value::Array(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(|line| Value::String(line.to_string()))
+                    .collect(),
+            )
+        } else {
+            Value::String(contents.trim_end_matches(['\r', '\n']).to_string())
+        };
+        table.insert(field, value);
+    }
+
+    for (_, value) in table.iter_mut() {
+        match value {
+            Value::Table(table) => resolve_secret_files(table)?,
+            Value::Array(array) => {
+                for value in array {
+                    if let Value::Table(table) = value {
+                        resolve_secret_files(table)?;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn invalid_override(variable: &str, reason: &str) -> MoorenewError {
+    MoorenewError::ConfigurationError(ConfigurationError::InvalidOverride {
+        variable: variable.to_string(),
+        reason: reason.to_string(),
+    })
+}
+
+fn set_path(table: &mut Table, path: &[String], raw_value: &str) -> Result<(), String> {
+    let (key, rest) = path.split_first().ok_or("empty path")?;
+
+    if rest.is_empty() {
+        let value = parse_value(raw_value, table.get(key));
+        // a value set through the environment replaces a secret file from the configuration
+        table.remove(&format!("{key}{FILE_SUFFIX}"));
+        table.insert(key.clone(), value);
+        return Ok(());
+    }
+
+    let child = table
+        .entry(key.clone())
+        .or_insert_with(|| Value::Table(Table::new()));
+    set_value_path(child, rest, raw_value)
+}
+
+fn set_value_path(value: &mut Value, path: &[String], raw_value: &str) -> Result<(), String> {
+    match value {
+        Value::Table(table) => set_path(table, path, raw_value),
+        Value::Array(array) => {
+            let (index, rest) = path.split_first().ok_or("empty path")?;
+            let index: usize = index
+                .parse()
+                .map_err(|_| format!("`{index}` is not an array index"))?;
+            if index > array.len() {
+                return Err(format!(
+                    "index {index} is out of range, the array has {} entries",
+                    array.len()
+                ));
+            }
+            if rest.is_empty() {
+                let value = parse_value(raw_value, array.get(index));
+                match array.get_mut(index) {
+                    Some(existing) => *existing = value,
+                    None => array.push(value),
+                }
+                return Ok(());
+            }
+            if index == array.len() {
+                array.push(Value::Table(Table::new()));
+            }
+            set_value_path(&mut array[index], rest, raw_value)
+        }
+        _ => Err("cannot set a field below a plain value".to_string()),
+    }
+}
+
+fn parse_value(raw_value: &str, existing: Option<&Value>) -> Value {
+    if matches!(existing, Some(Value::String(_))) {
+        return Value::String(raw_value.to_string());
+    }
+
+    toml::from_str::<Table>(&format!("value = {raw_value}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw_value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_env_overrides, resolve_secret_files};
+    use crate::utils::testutil::TempDir;
+    use std::fs;
+    use toml::{Table, Value};
+
+    fn variables(variables: &[(&str, &str)]) -> Vec<(String, String)> {
+        variables
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_apply_env_overrides() {
+        let mut configuration: Table = toml::from_str(
+            r#"
+buzz_urls = []
+
+[logging]
+level = "info"
+
+[[jobs]]
+name = "example.com"
+sftp_port = 22
+sftp_user = "1234"
+"#,
+        )
+        .unwrap();
+
+        apply_env_overrides(
+            &mut configuration,
+            variables(&[
+                ("MOORENEW_CONFIG", "/etc/moorenew/config.toml"),
+                ("MOORENEW_LOGGING__LEVEL", "debug"),
+                ("MOORENEW_LOGGING__LOKI__PASSWORD", "secret"),
+                ("MOORENEW_JOBS__0__SFTP_PORT", "2222"),
+                ("MOORENEW_JOBS__0__SFTP_USER", "4321"),
+                ("MOORENEW_JOBS__1__NAME", "example.org"),
+                ("MOORENEW_BUZZ_URLS", r#"["ntfy://ntfy.sh/moorenew"]"#),
+                ("OTHER_VARIABLE", "ignored"),
+            ]),
+        )
+        .unwrap();
+
+        assert!(!configuration.contains_key("config"));
+        assert_eq!(configuration["logging"]["level"].as_str(), Some("debug"));
+        assert_eq!(
+            configuration["logging"]["loki"]["password"].as_str(),
+            Some("secret")
+        );
+        assert_eq!(
+            configuration["jobs"][0]["sftp_port"].as_integer(),
+            Some(2222)
+        );
+        assert_eq!(configuration["jobs"][0]["sftp_user"].as_str(), Some("4321"));
+        assert_eq!(
+            configuration["jobs"][1]["name"].as_str(),
+            Some("example.org")
+        );
+        assert_eq!(
+            configuration["buzz_urls"],
+            Value::Array(vec![Value::String("ntfy://ntfy.sh/moorenew".to_string())])
+        );
+
+        assert!(
+            apply_env_overrides(
+                &mut configuration,
+                variables(&[("MOORENEW_JOBS__5__NAME", "example.net")])
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_resolve_secret_files() {
+        let root = TempDir::new("secrets");
+        fs::write(root.join("password"), "secret\n").unwrap();
+        fs::write(
+            root.join("buzz_urls"),
+            "gotify://gotify.example.com/token\n\nntfy://ntfy.sh/moorenew\n",
+        )
+        .unwrap();
+
+        let mut configuration: Table = toml::from_str(&format!(
+            r#"
+buzz_urls_file = "{root}/buzz_urls"
+
+[logging.loki]
+password_file = "{root}/password"
+"#,
+            root = root.display()
+        ))
+        .unwrap();
+        resolve_secret_files(&mut configuration).unwrap();
+
+        assert_eq!(
+            configuration["logging"]["loki"]["password"].as_str(),
+            Some("secret")
+        );
+        assert!(
+            !configuration["logging"]["loki"]
+                .as_table()
+                .unwrap()
+                .contains_key("password_file")
+        );
+        assert_eq!(configuration["buzz_urls"].as_array().unwrap().len(), 2);
+    }
+}
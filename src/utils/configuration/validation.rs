@@ -0,0 +1,520 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use url::Url;
+
+use super::configuration::{
+    AcmeChallengeConfiguration, AcmeConfiguration, Configuration, ContainerAction,
+    JobConfiguration, SourceConfiguration, SshAuthMethod, parse_config_with_overrides,
+};
+use crate::utils::acme::TsigKey;
+use crate::utils::errors::{ConfigurationError, MoorenewError};
+use crate::utils::mailcow;
+use crate::utils::sshconfig::{SshConfig, parse_jump_host};
+
+/// Notification schemes supported by buzz.
+const SUPPORTED_BUZZ_SCHEMES: &[&str] = &["gotify", "ntfy"];
+
+const LOGGING_LEVELS: &[&str] = &["trace", "debug", "info", "warn", "error"];
+
+/// A single problem found by [`validate_config`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationProblem {
+    /// Field the problem belongs to, e.g. `jobs[0].private_key_path`.
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// validate_config checks the configuration file for everything which would otherwise only fail
+/// during a run: TOML syntax and types (reported with line and column), key files and their
+/// permissions, notification URLs, the Loki URL and the certificate directories. The `MOORENEW_*`
+/// overrides of `variables` are applied first. Returns every problem found.
+pub fn validate_config(
+    config_path: &Path,
+    variables: impl IntoIterator<Item = (String, String)>,
+) -> Vec<ValidationProblem> {
+    let config_contents = match fs::read_to_string(config_path) {
+        Ok(config_contents) => config_contents,
+        Err(e) => {
+            return vec![problem(
+                &config_path.display().to_string(),
+                format!("could not read the configuration file: {e}"),
+            )];
+        }
+    };
+
+    let configuration = match parse_config_with_overrides(&config_contents, variables) {
+        Ok(configuration) => configuration,
+        Err(MoorenewError::ConfigurationError(ConfigurationError::ConfigParsing(e))) => {
+            // errors found after applying the overrides carry no position, so parse the plain
+            // file again to locate them
+            let located = e
+                .span()
+                .map(|span| (e.message().to_string(), span.start))
+                .or_else(|| {
+                    toml::from_str::<Configuration>(&config_contents)
+                        .err()
+                        .and_then(|e| e.span().map(|span| (e.message().to_string(), span.start)))
+                });
+            let (field, message) = match located {
+                Some((message, offset)) => {
+                    let (line, column) = line_and_column(&config_contents, offset);
+                    (format!("line {line}, column {column}"), message)
+                }
+                None => (config_path.display().to_string(), e.message().to_string()),
+            };
+            return vec![problem(&field, message)];
+        }
+        Err(e) => {
+            return vec![problem(&config_path.display().to_string(), e.to_string())];
+        }
+    };
+
+    let mut problems = Vec::new();
+    let mut configuration = configuration;
+    for (index, job) in configuration.jobs.iter_mut().enumerate() {
+        if let Err(e) = mailcow::discover(job) {
+            let message = match &e {
+                MoorenewError::ConfigurationError(ConfigurationError::MailcowConf {
+                    path,
+                    error,
+                }) => format!("could not read {path}: {error}"),
+                e => e.to_string(),
+            };
+            problems.push(problem(&format!("jobs[{index}].mailcow_dir"), message));
+        }
+    }
+
+    problems.extend(validate_configuration(&configuration));
+    problems
+}
+
+/// Checks an already parsed configuration, see [`validate_config`].
+pub fn validate_configuration(configuration: &Configuration) -> Vec<ValidationProblem> {
+    let mut problems = Vec::new();
+
+    if configuration.jobs.is_empty() {
+        problems.push(problem("jobs", "no jobs configured"));
+    }
+
+    let mut job_names = HashSet::new();
+    for (index, job) in configuration.jobs.iter().enumerate() {
+        let field = |name: &str| format!("jobs[{index}].{name}");
+
+        if !job_names.insert(job.name.as_str()) {
+            problems.push(problem(
+                &field("name"),
+                format!("job name `{}` is used more than once", job.name),
+            ));
+        }
+
+        if job.uses_ssh() {
+            if job.sftp_host.is_empty() {
+                problems.push(problem(&field("sftp_host"), "no sftp host configured"));
+            }
+            check_authentication(&mut problems, &field, job);
+            for (jump_index, jump_host) in job.jump_hosts.iter().enumerate() {
+                if parse_jump_host(jump_host).is_err() {
+                    problems.push(problem(
+                        &field(&format!("jump_hosts[{jump_index}]")),
+                        format!("invalid jump host `{jump_host}`, expected [user@]host[:port]"),
+                    ));
+                }
+            }
+        }
+        check_writable_dir(&mut problems, &field("mail_cert_path"), &job.mail_cert_path);
+
+        if let SourceConfiguration::Https { url, .. } = &job.source {
+            match Url::parse(url) {
+                Ok(url) if url.scheme() == "https" => {}
+                Ok(url) => problems.push(problem(
+                    &field("source.url"),
+                    format!("unsupported scheme `{}`, use https", url.scheme()),
+                )),
+                Err(e) => problems.push(problem(&field("source.url"), format!("invalid url: {e}"))),
+            }
+        }
+
+        if let SourceConfiguration::Acme(acme) = &job.source {
+            check_acme(&mut problems, &field("source"), acme);
+            if job.hostnames.is_empty() {
+                problems.push(problem(
+                    &field("hostnames"),
+                    "the acme source needs hostnames to order a certificate for",
+                ));
+            }
+        }
+
+        if job.source.is_sftp() && job.npm_cert_path.is_empty() && job.npm_domain.is_none() {
+            problems.push(problem(
+                &field("npm_cert_path"),
+                "neither npm_cert_path nor npm_domain is set",
+            ));
+        }
+
+        for (container_index, container) in job.containers.iter().enumerate() {
+            if container.action == ContainerAction::Exec && container.reload_command().is_none() {
+                problems.push(problem(
+                    &field(&format!("containers[{container_index}].command")),
+                    format!(
+                        "no reload command configured for container `{}`",
+                        container.name
+                    ),
+                ));
+            }
+        }
+
+        let mut domains = HashSet::new();
+        for (domain_index, domain) in job.domains.iter().enumerate() {
+            let domain_field = field(&format!("domains[{domain_index}].domain"));
+            if domain.domain.is_empty()
+                || domain.domain.starts_with('.')
+                || domain.domain.contains(['/', '\\'])
+            {
+                problems.push(problem(
+                    &domain_field,
+                    format!(
+                        "`{}` is not a plain directory name in mail_cert_path",
+                        domain.domain
+                    ),
+                ));
+            } else if !domains.insert(domain.domain.as_str()) {
+                problems.push(problem(
+                    &domain_field,
+                    format!("domain `{}` is configured more than once", domain.domain),
+                ));
+            }
+            if let Some(SourceConfiguration::Acme(acme)) = &domain.source {
+                check_acme(
+                    &mut problems,
+                    &field(&format!("domains[{domain_index}].source")),
+                    acme,
+                );
+            }
+        }
+
+        if let Some(verification) = &job.tls_verification
+            && verification.ports.is_empty()
+        {
+            problems.push(problem(
+                &field("tls_verification.ports"),
+                "no ports configured",
+            ));
+        }
+    }
+
+    if !LOGGING_LEVELS.contains(&configuration.logging.level.to_lowercase().as_str()) {
+        problems.push(problem(
+            "logging.level",
+            format!(
+                "unknown level `{}`, use one of {}",
+                configuration.logging.level,
+                LOGGING_LEVELS.join(", ")
+            ),
+        ));
+    }
+
+    if let Some(loki) = &configuration.logging.loki {
+        match Url::parse(&loki.url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+            Ok(url) => problems.push(problem(
+                "logging.loki.url",
+                format!("unsupported scheme `{}`, use http or https", url.scheme()),
+            )),
+            Err(e) => problems.push(problem("logging.loki.url", format!("invalid url: {e}"))),
+        }
+    }
+
+    for (index, buzz_url) in configuration.buzz_urls.iter().enumerate() {
+        let field = format!("buzz_urls[{index}]");
+        match Url::parse(buzz_url) {
+            Ok(url) if SUPPORTED_BUZZ_SCHEMES.contains(&url.scheme()) => {}
+            Ok(url) => problems.push(problem(
+                &field,
+                format!(
+                    "unsupported scheme `{}`, use one of {}",
+                    url.scheme(),
+                    SUPPORTED_BUZZ_SCHEMES.join(", ")
+                ),
+            )),
+            // the url may contain credentials, so it is not part of the message
+            Err(e) => problems.push(problem(&field, format!("invalid url: {e}"))),
+        }
+    }
+
+    problems
+}
+
+fn check_acme(problems: &mut Vec<ValidationProblem>, field: &str, acme: &AcmeConfiguration) {
+    let urls = [
+        ("directory_url", Some(&acme.directory_url)),
+        ("dry_run_directory_url", acme.dry_run_directory_url.as_ref()),
+    ];
+    for (name, url) in urls {
+        let Some(url) = url else {
+            continue;
+        };
+        match Url::parse(url) {
+            Ok(url) if url.scheme() == "https" => {}
+            Ok(url) => problems.push(problem(
+                &format!("{field}.{name}"),
+                format!("unsupported scheme `{}`, use https", url.scheme()),
+            )),
+            Err(e) => problems.push(problem(
+                &format!("{field}.{name}"),
+                format!("invalid url: {e}"),
+            )),
+        }
+    }
+
+    if let AcmeChallengeConfiguration::Dns01 {
+        tsig_key_name,
+        tsig_algorithm,
+        tsig_secret,
+        ..
+    } = &acme.challenge
+        && let Err(e) = TsigKey::new(tsig_key_name, tsig_algorithm, tsig_secret)
+    {
+        problems.push(problem(&format!("{field}.challenge"), e.to_string()));
+    }
+}
+
+/// Checks that at least one SSH authentication method is usable and that the key files exist.
+fn check_authentication(
+    problems: &mut Vec<ValidationProblem>,
+    field: &dyn Fn(&str) -> String,
+    job: &JobConfiguration,
+) {
+    let uses_key_file = job.auth_methods.contains(&SshAuthMethod::KeyFile);
+    if uses_key_file && !job.private_key_path.is_empty() {
+        check_key_file(
+            problems,
+            &field("private_key_path"),
+            &job.private_key_path,
+            true,
+        );
+        if !job.public_key_path.is_empty() {
+            check_key_file(
+                problems,
+                &field("public_key_path"),
+                &job.public_key_path,
+                false,
+            );
+        }
+    }
+
+    // without private_key_path the IdentityFiles of the ssh config are tried
+    let ssh_config = match job.ssh_config_path() {
+        Ok(Some(path)) => SshConfig::load(&path),
+        Ok(None) => Ok(SshConfig::default()),
+        Err(e) => Err(e),
+    };
+    let identity_files = match ssh_config {
+        Ok(ssh_config) => ssh_config.host(&job.sftp_host).identity_files,
+        Err(e) => {
+            let message = match &e {
+                MoorenewError::ConfigurationError(ConfigurationError::SshConfig {
+                    path,
+                    error,
+                }) => {
+                    format!("could not read {path}: {error}")
+                }
+                e => e.to_string(),
+            };
+            problems.push(problem(&field("ssh_config_path"), message));
+            Vec::new()
+        }
+    };
+
+    let usable = job.auth_methods.iter().any(|method| match method {
+        SshAuthMethod::Agent => true,
+        SshAuthMethod::Key => job.private_key.is_some(),
+        SshAuthMethod::KeyFile => !job.private_key_path.is_empty() || !identity_files.is_empty(),
+    });
+    if !usable {
+        problems.push(problem(
+            &field("auth_methods"),
+            "no ssh authentication method is usable, configure private_key_path, private_key or add agent",
+        ));
+    }
+}
+
+fn check_key_file(problems: &mut Vec<ValidationProblem>, field: &str, path: &str, private: bool) {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            problems.push(problem(field, format!("could not access {path}: {e}")));
+            return;
+        }
+    };
+
+    if !metadata.is_file() {
+        problems.push(problem(field, format!("{path} is not a file")));
+        return;
+    }
+
+    let mode = metadata.permissions().mode() & 0o777;
+    if private && mode & 0o077 != 0 {
+        problems.push(problem(
+            field,
+            format!("{path} is accessible by other users (mode {mode:o}), restrict it to 600"),
+        ));
+    }
+}
+
+fn check_writable_dir(problems: &mut Vec<ValidationProblem>, field: &str, path: &str) {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => {}
+        Ok(_) => {
+            problems.push(problem(field, format!("{path} is not a directory")));
+            return;
+        }
+        Err(e) => {
+            problems.push(problem(field, format!("could not access {path}: {e}")));
+            return;
+        }
+    }
+
+    // permission bits do not tell if the directory is writable for the current user, e.g. on
+    // read-only mounts, so try to create a file
+    let probe = Path::new(path).join(format!(".moorenew-validate-{}", std::process::id()));
+    match OpenOptions::new().write(true).create_new(true).open(&probe) {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+        }
+        Err(e) => problems.push(problem(field, format!("{path} is not writable: {e}"))),
+    }
+}
+
+fn problem(field: &str, message: impl Into<String>) -> ValidationProblem {
+    ValidationProblem {
+        field: field.to_string(),
+        message: message.into(),
+    }
+}
+
+/// Converts a byte offset into a 1-based line and column.
+fn line_and_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map(|line| line.chars().count())
+        .unwrap_or_default()
+        + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_config;
+    use crate::utils::testutil::TempDir;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_validate_config() {
+        let root = TempDir::new("validate");
+        let ssl = root.join("ssl");
+        fs::create_dir_all(&ssl).unwrap();
+        let private_key = root.join("moorenew");
+        fs::write(&private_key, "key").unwrap();
+        fs::set_permissions(&private_key, fs::Permissions::from_mode(0o644)).unwrap();
+        let config_path = root.join("config.toml");
+
+        fs::write(&config_path, "buzz_urls = [\n[logging]\n").unwrap();
+        let problems = validate_config(&config_path, Vec::new());
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].field.starts_with("line 2, column"));
+
+        fs::write(
+            &config_path,
+            format!(
+                r#"
+buzz_urls = ["gotify://gotify.example.com/token", "mailto://admin@example.com"]
+
+[logging]
+level = "info"
+
+[logging.loki]
+url = "not a url"
+user = ""
+password = ""
+
+[[jobs]]
+name = "example.com"
+sftp_host = "npm.example.com"
+sftp_user = "mailcow"
+private_key_path = "{private_key}"
+public_key_path = "{private_key}.pub"
+npm_cert_path = "/etc/letsencrypt/live/npm-1"
+mail_cert_path = "{ssl}"
+
+[[jobs.domains]]
+domain = "example.org"
+npm_cert_path = "/etc/letsencrypt/live/npm-2"
+
+[[jobs.domains]]
+domain = "../example.net"
+npm_cert_path = "/etc/letsencrypt/live/npm-3"
+
+[[jobs]]
+name = "example.io"
+mail_cert_path = "{ssl}"
+
+[jobs.source]
+type = "acme"
+directory_url = "http://acme.example.io/directory"
+
+[jobs.source.challenge]
+type = "dns-01"
+nameserver = "ns1.example.io:53"
+zone = "example.io"
+tsig_key_name = "moorenew"
+tsig_secret = "not base64"
+
+[[jobs]]
+name = "example.net"
+sftp_host = "npm.example.net"
+sftp_user = "mailcow"
+auth_methods = ["key"]
+ssh_config_path = "none"
+jump_hosts = ["admin@bastion.example.net:2222", "bastion:ssh"]
+npm_cert_path = "/etc/letsencrypt/live/npm-1"
+mail_cert_path = "{ssl}"
+"#,
+                private_key = private_key.display(),
+                ssl = ssl.display()
+            ),
+        )
+        .unwrap();
+        let fields: Vec<String> = validate_config(&config_path, Vec::new())
+            .into_iter()
+            .map(|problem| problem.field)
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                "jobs[0].private_key_path",
+                "jobs[0].public_key_path",
+                "jobs[0].domains[1].domain",
+                "jobs[1].source.directory_url",
+                "jobs[1].source.challenge",
+                "jobs[1].hostnames",
+                "jobs[2].auth_methods",
+                "jobs[2].jump_hosts[1]",
+                "logging.loki.url",
+                "buzz_urls[1]"
+            ]
+        );
+    }
+}
@@ -0,0 +1,470 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, instrument};
+
+use crate::utils::errors::{DockerError, MoorenewError};
+use crate::utils::http::{self, HttpResponse};
+
+const DEFAULT_SOCKET_PATH: &str = "/var/run/docker.sock";
+const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+/// Seconds docker waits for a container to stop before killing it on restart.
+const STOP_TIMEOUT_SECS: u64 = 10;
+const READY_TIMEOUT: Duration = Duration::from_secs(120);
+const READY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Client for the Docker Engine API reachable through a unix socket.
+pub struct DockerClient {
+    socket_path: PathBuf,
+    compose_project: Option<String>,
+    ready_timeout: Duration,
+    ready_poll_interval: Duration,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ContainerSummary {
+    #[serde(rename = "Id")]
+    pub id: String,
+    #[serde(rename = "Names", default)]
+    pub names: Vec<String>,
+    #[serde(rename = "Labels", default)]
+    pub labels: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContainerInspect {
+    #[serde(rename = "State")]
+    state: ContainerState,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContainerState {
+    #[serde(rename = "Status")]
+    status: String,
+    #[serde(rename = "Health")]
+    health: Option<ContainerHealth>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContainerHealth {
+    #[serde(rename = "Status")]
+    status: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExecCreated {
+    #[serde(rename = "Id")]
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExecInspect {
+    #[serde(rename = "ExitCode")]
+    exit_code: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiErrorMessage {
+    message: String,
+}
+
+impl DockerClient {
+    pub fn new(socket_path: &Path) -> DockerClient {
+        DockerClient {
+            socket_path: socket_path.to_path_buf(),
+            compose_project: None,
+            ready_timeout: READY_TIMEOUT,
+            ready_poll_interval: READY_POLL_INTERVAL,
+        }
+    }
+
+    /// Uses the socket of `DOCKER_HOST` if it is set to a `unix://` address and
+    /// `/var/run/docker.sock` otherwise.
+    pub fn from_env() -> DockerClient {
+        let socket_path = std::env::var("DOCKER_HOST")
+            .ok()
+            .and_then(|host| host.strip_prefix("unix://").map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET_PATH));
+        DockerClient::new(&socket_path)
+    }
+
+    /// Only matches compose services of `project`, so containers of other compose projects with
+    /// the same service names are ignored. The name is normalized the way docker compose does.
+    pub fn with_compose_project(mut self, project: Option<&str>) -> DockerClient {
+        self.compose_project = project.map(|project| {
+            project
+                .to_lowercase()
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+                .collect()
+        });
+        self
+    }
+
+    /// find_container looks up a container by its exact name first. If no container has that
+    /// name, the container whose compose service label equals `name` is used, which matches
+    /// prefixed compose names like `mailcowdockerized-postfix-mailcow-1`. If a compose project is
+    /// set, the container also has to belong to it.
+    pub fn find_container(&self, name: &str) -> Result<ContainerSummary, MoorenewError> {
+        let response = self.request("GET", "/containers/json?all=true", None)?;
+        let containers: Vec<ContainerSummary> = parse_json(&response)?;
+
+        let name = name.trim_start_matches('/');
+        if let Some(container) = containers.iter().find(|container| {
+            container
+                .names
+                .iter()
+                .any(|n| n.trim_start_matches('/') == name)
+        }) {
+            return Ok(container.clone());
+        }
+
+        let mut service_matches = containers.into_iter().filter(|container| {
+            container
+                .labels
+                .get(COMPOSE_SERVICE_LABEL)
+                .is_some_and(|service| service == name)
+                && self.compose_project.as_ref().is_none_or(|project| {
+                    container.labels.get(COMPOSE_PROJECT_LABEL) == Some(project)
+                })
+        });
+
+        match (service_matches.next(), service_matches.next()) {
+            (Some(container), None) => Ok(container),
+            (None, _) => Err(MoorenewError::Docker(DockerError::ContainerNotFound(
+                name.to_string(),
+            ))),
+            (Some(_), Some(_)) => Err(MoorenewError::Docker(DockerError::AmbiguousContainer(
+                name.to_string(),
+            ))),
+        }
+    }
+
+    /// restart_container restarts the container and waits until it is running again. Containers
+    /// with a health check have to report healthy.
+    #[instrument(skip(self))]
+    pub fn restart_container(&self, name: &str) -> Result<(), MoorenewError> {
+        let container = self.find_container(name)?;
+        debug!(id = %container.id, "found container");
+
+        let response = self.request(
+            "POST",
+            &format!("/containers/{}/restart?t={STOP_TIMEOUT_SECS}", container.id),
+            None,
+        )?;
+        check_status(&response)?;
+        info!("restarted container, waiting for it to become ready");
+
+        self.wait_until_ready(name, &container.id)
+    }
+
+    /// exec runs `command` inside the container, waits for it to finish and fails if it exits
+    /// with a non-zero status.
+    #[instrument(skip(self))]
+    pub fn exec(&self, name: &str, command: &[String]) -> Result<(), MoorenewError> {
+        let container = self.find_container(name)?;
+
+        let body = serde_json::json!({
+            "AttachStdout": true,
+            "AttachStderr": true,
+            "Cmd": command,
+        })
+        .to_string();
+        let response = self.request(
+            "POST",
+            &format!("/containers/{}/exec", container.id),
+            Some(body.as_bytes()),
+        )?;
+        let exec: ExecCreated = parse_json(&response)?;
+
+        let response = self.request(
+            "POST",
+            &format!("/exec/{}/start", exec.id),
+            Some(br#"{"Detach": false, "Tty": false}"#),
+        )?;
+        check_status(&response)?;
+        let output = demultiplex_output(&response.body);
+
+        let response = self.request("GET", &format!("/exec/{}/json", exec.id), None)?;
+        let inspect: ExecInspect = parse_json(&response)?;
+
+        match inspect.exit_code {
+            Some(0) => {
+                debug!(output = %output.trim(), "command finished successfully");
+                Ok(())
+            }
+            exit_code => Err(MoorenewError::Docker(DockerError::ExecFailed {
+                name: name.to_string(),
+                command: command.join(" "),
+                exit_code: exit_code.unwrap_or(-1),
+                output: output.trim().to_string(),
+            })),
+        }
+    }
+
+    /// signal sends `signal` (for example `HUP`) to the main process of the container.
+    #[instrument(skip(self))]
+    pub fn signal(&self, name: &str, signal: &str) -> Result<(), MoorenewError> {
+        let container = self.find_container(name)?;
+
+        let response = self.request(
+            "POST",
+            &format!("/containers/{}/kill?signal={signal}", container.id),
+            None,
+        )?;
+        check_status(&response)
+    }
+
+    fn wait_until_ready(&self, name: &str, id: &str) -> Result<(), MoorenewError> {
+        let started = Instant::now();
+        loop {
+            let response = self.request("GET", &format!("/containers/{id}/json"), None)?;
+            let inspect: ContainerInspect = parse_json(&response)?;
+
+            let state = match &inspect.state.health {
+                Some(health) => format!("{} ({})", inspect.state.status, health.status),
+                None => inspect.state.status.clone(),
+            };
+            let ready = inspect.state.status == "running"
+                && inspect
+                    .state
+                    .health
+                    .as_ref()
+                    .is_none_or(|health| health.status == "healthy");
+
+            if ready {
+                debug!(state = %state, "container is ready");
+                return Ok(());
+            }
+
+            if started.elapsed() >= self.ready_timeout {
+                return Err(MoorenewError::Docker(DockerError::NotReady {
+                    name: name.to_string(),
+                    state,
+                }));
+            }
+
+            debug!(state = %state, "container is not ready yet");
+            sleep(self.ready_poll_interval);
+        }
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&[u8]>,
+    ) -> Result<HttpResponse, MoorenewError> {
+        let stream = UnixStream::connect(&self.socket_path)
+            .map_err(|e| MoorenewError::Docker(DockerError::Connection(e)))?;
+        stream
+            .set_read_timeout(Some(REQUEST_TIMEOUT))
+            .map_err(|e| MoorenewError::Docker(DockerError::Connection(e)))?;
+
+        let headers: &[(&str, &str)] = if body.is_some() {
+            &[("Content-Type", "application/json")]
+        } else {
+            &[]
+        };
+        http::request(stream, method, "docker", path, headers, body)
+            .map_err(|e| MoorenewError::Docker(DockerError::Connection(e)))
+    }
+}
+
+/// Converts the multiplexed stdout/stderr stream of a container without tty into text. Every frame
+/// starts with an 8 byte header whose last 4 bytes contain the big endian frame length.
+fn demultiplex_output(stream: &[u8]) -> String {
+    let mut output = Vec::new();
+    let mut rest = stream;
+    while rest.len() >= 8 {
+        let length = u32::from_be_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        let end = (8 + length).min(rest.len());
+        output.extend_from_slice(&rest[8..end]);
+        rest = &rest[end..];
+    }
+    String::from_utf8_lossy(&output).to_string()
+}
+
+fn check_status(response: &HttpResponse) -> Result<(), MoorenewError> {
+    if response.is_success() {
+        return Ok(());
+    }
+
+    let message = serde_json::from_slice::<ApiErrorMessage>(&response.body)
+        .map(|error| error.message)
+        .unwrap_or_else(|_| String::from_utf8_lossy(&response.body).trim().to_string());
+    Err(MoorenewError::Docker(DockerError::Api {
+        status: response.status,
+        message,
+    }))
+}
+
+fn parse_json<T: serde::de::DeserializeOwned>(response: &HttpResponse) -> Result<T, MoorenewError> {
+    check_status(response)?;
+    serde_json::from_slice(&response.body).map_err(|e| MoorenewError::Docker(DockerError::Json(e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DockerClient;
+    use crate::utils::errors::{DockerError, MoorenewError};
+    use crate::utils::testutil::TempDir;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::os::unix::net::UnixListener;
+    use std::thread::JoinHandle;
+    use std::time::Duration;
+
+    const CONTAINERS: &str = r#"[
+        {"Id": "aaa", "Names": ["/mailcowdockerized-postfix-mailcow-1"], "Labels": {"com.docker.compose.service": "postfix-mailcow", "com.docker.compose.project": "mailcowdockerized"}},
+        {"Id": "bbb", "Names": ["/dovecot-mailcow"], "Labels": {}},
+        {"Id": "ccc", "Names": ["/dovecot-mailcow-old"], "Labels": {"com.docker.compose.service": "dovecot-mailcow"}},
+        {"Id": "ddd", "Names": ["/mailcowtwo-postfix-mailcow-1"], "Labels": {"com.docker.compose.service": "postfix-mailcow", "com.docker.compose.project": "mailcowtwo"}}
+    ]"#;
+
+    /// Serves one canned response per connection on `docker.sock` in the returned directory and
+    /// returns the received request lines.
+    fn fake_docker(
+        name: &str,
+        responses: Vec<(u16, &'static str)>,
+    ) -> (TempDir, JoinHandle<Vec<String>>) {
+        let root = TempDir::new(&format!("docker-{name}"));
+        let listener = UnixListener::bind(root.join("docker.sock")).unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(length) = line.strip_prefix("Content-Length: ") {
+                        content_length = length.trim().parse().unwrap();
+                    }
+                    if line.trim_end().is_empty() {
+                        break;
+                    }
+                }
+                let mut request_body = vec![0; content_length];
+                reader.read_exact(&mut request_body).unwrap();
+                requests.push(request_line.trim_end().to_string());
+                write!(
+                    stream,
+                    "HTTP/1.1 {status} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+            requests
+        });
+
+        (root, handle)
+    }
+
+    #[test]
+    fn test_find_container() {
+        let (root, server) = fake_docker("find", vec![(200, CONTAINERS); 5]);
+        let client = DockerClient::new(&root.join("docker.sock"));
+
+        assert!(matches!(
+            client.find_container("postfix-mailcow"),
+            Err(MoorenewError::Docker(DockerError::AmbiguousContainer(_)))
+        ));
+        assert_eq!(client.find_container("dovecot-mailcow").unwrap().id, "bbb");
+        assert!(matches!(
+            client.find_container("mailcow"),
+            Err(MoorenewError::Docker(DockerError::ContainerNotFound(_)))
+        ));
+
+        let client = client.with_compose_project(Some("MailcowTwo"));
+        assert_eq!(client.find_container("postfix-mailcow").unwrap().id, "ddd");
+        assert!(matches!(
+            client.find_container("dovecot-mailcow-old"),
+            Ok(container) if container.id == "ccc"
+        ));
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_restart_container() {
+        let (root, server) = fake_docker(
+            "restart",
+            vec![
+                (200, CONTAINERS),
+                (204, ""),
+                (200, r#"{"State": {"Status": "restarting"}}"#),
+                (
+                    200,
+                    r#"{"State": {"Status": "running", "Health": {"Status": "healthy"}}}"#,
+                ),
+                (200, CONTAINERS),
+                (500, r#"{"message": "cannot restart container"}"#),
+            ],
+        );
+        let mut client = DockerClient::new(&root.join("docker.sock"))
+            .with_compose_project(Some("mailcowdockerized"));
+        client.ready_poll_interval = Duration::from_millis(10);
+
+        client.restart_container("postfix-mailcow").unwrap();
+        match client.restart_container("dovecot-mailcow") {
+            Err(MoorenewError::Docker(DockerError::Api { status, message })) => {
+                assert_eq!(status, 500);
+                assert_eq!(message, "cannot restart container");
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[1], "POST /containers/aaa/restart?t=10 HTTP/1.1");
+        assert_eq!(requests[3], "GET /containers/aaa/json HTTP/1.1");
+        assert_eq!(requests[5], "POST /containers/bbb/restart?t=10 HTTP/1.1");
+    }
+
+    #[test]
+    fn test_exec_and_signal() {
+        let (root, server) = fake_docker(
+            "exec",
+            vec![
+                (200, CONTAINERS),
+                (201, r#"{"Id": "exec1"}"#),
+                (200, "\u{1}\0\0\0\0\0\0\u{7}reload\n"),
+                (200, r#"{"ExitCode": 0, "Running": false}"#),
+                (200, CONTAINERS),
+                (201, r#"{"Id": "exec2"}"#),
+                (200, "\u{2}\0\0\0\0\0\0\u{6}failed"),
+                (200, r#"{"ExitCode": 1, "Running": false}"#),
+                (200, CONTAINERS),
+                (204, ""),
+            ],
+        );
+        let client = DockerClient::new(&root.join("docker.sock"))
+            .with_compose_project(Some("mailcowdockerized"));
+        let command = vec!["postfix".to_string(), "reload".to_string()];
+
+        client.exec("postfix-mailcow", &command).unwrap();
+        match client.exec("postfix-mailcow", &command) {
+            Err(MoorenewError::Docker(DockerError::ExecFailed {
+                exit_code, output, ..
+            })) => {
+                assert_eq!(exit_code, 1);
+                assert_eq!(output, "failed");
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+        client.signal("dovecot-mailcow", "HUP").unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[1], "POST /containers/aaa/exec HTTP/1.1");
+        assert_eq!(requests[2], "POST /exec/exec1/start HTTP/1.1");
+        assert_eq!(requests[9], "POST /containers/bbb/kill?signal=HUP HTTP/1.1");
+    }
+}
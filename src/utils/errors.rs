@@ -1,5 +1,20 @@
 use thiserror::Error;
 
+/// Exit codes of moorenew, documented in `docs/running.md`.
+pub const EXIT_SUCCESS: u8 = 0;
+pub const EXIT_FAILURE: u8 = 1;
+pub const EXIT_UP_TO_DATE: u8 = 10;
+pub const EXIT_CONFIGURATION: u8 = 20;
+pub const EXIT_SSH_CONNECTION: u8 = 30;
+pub const EXIT_SSH_AUTHENTICATION: u8 = 31;
+pub const EXIT_HOST_KEY: u8 = 32;
+pub const EXIT_TRANSFER: u8 = 40;
+pub const EXIT_CERTIFICATE_VALIDATION: u8 = 50;
+pub const EXIT_INSTALLATION: u8 = 60;
+pub const EXIT_CONTAINERS_FAILED: u8 = 70;
+pub const EXIT_TLS_VERIFICATION: u8 = 80;
+pub const EXIT_EXPIRING: u8 = 90;
+
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum MoorenewError {
@@ -9,6 +24,18 @@ pub enum MoorenewError {
     #[error("failed to create systemd service file")]
     ServiceFileCreationFailed(#[source] std::io::Error),
 
+    #[error("failed to create openrc init script")]
+    InitScriptCreationFailed(#[source] std::io::Error),
+
+    #[error("failed to create crond periodic script")]
+    PeriodicScriptCreationFailed(#[source] std::io::Error),
+
+    #[error("unknown service provider `{0}`, use systemd or openrc")]
+    UnknownServiceProvider(String),
+
+    #[error("could not detect the service provider, pass it with --provider")]
+    ServiceProviderUndetectable,
+
     #[error("see components field for more information")]
     ServiceConfigGenerationFailed { components: Vec<String> },
 
@@ -21,6 +48,41 @@ pub enum MoorenewError {
     #[error("could not connect to ssh host")]
     SSHConnectError(#[source] std::io::Error),
 
+    #[error("could not create ssh session")]
+    SshSession(#[source] ssh2::Error),
+
+    #[error("ssh handshake failed")]
+    SshHandshake(#[source] ssh2::Error),
+
+    #[error("could not open a tunnel to {host} through the jump host")]
+    SshTunnel {
+        host: String,
+        #[source]
+        error: ssh2::Error,
+    },
+
+    #[error("the certificate source reads from {0}, but the job is not connected to it")]
+    SshNotConnected(String),
+
+    #[error("ssh authentication failed, tried {}", attempts.join("; "))]
+    SshAuthentication { attempts: Vec<String> },
+
+    #[error("host key of {host} does not match (expected {expected}, got {actual})")]
+    HostKeyMismatch {
+        host: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("host key {fingerprint} of {host} is not trusted")]
+    HostKeyUnknown { host: String, fingerprint: String },
+
+    #[error("could not verify host key")]
+    HostKeyVerification(#[source] ssh2::Error),
+
+    #[error("could not add host key to known_hosts")]
+    KnownHostsUpdate(#[source] std::io::Error),
+
     #[error("error executing remote command")]
     SSHExecutionError(#[source] ssh2::Error),
 
@@ -33,12 +95,79 @@ pub enum MoorenewError {
     #[error("error transferring file")]
     FileTransfer(#[source] std::io::Error),
 
+    #[error("could not start sftp subsystem")]
+    Sftp(#[source] ssh2::Error),
+
+    #[error("could not read certificates from {location}")]
+    CertificateSource {
+        location: String,
+        #[source]
+        error: std::io::Error,
+    },
+
+    #[error("could not open remote file {path}")]
+    SftpOpen {
+        path: String,
+        #[source]
+        error: ssh2::Error,
+    },
+
+    #[error("remote command `{command}` exited with {exit_status}")]
+    RemoteCommandFailed { command: String, exit_status: i32 },
+
     #[error("invalid utf8 output from `{command}`")]
     Utf8Output {
         command: String,
         error: std::string::FromUtf8Error,
     },
 
+    #[error("certificate validation failed: {0}")]
+    CertificateValidation(#[source] CertificateValidationError),
+
+    #[error("could not install certificates")]
+    CertificateInstallation(#[source] std::io::Error),
+
+    #[error("could not access certificate backup")]
+    CertificateBackup(#[source] std::io::Error),
+
+    #[error("no valid certificate for {domain} found in {path}")]
+    CertificateNotFound { domain: String, path: String },
+
+    #[error("no certificate backup available")]
+    NoBackupAvailable,
+
+    #[error("could not access state file {path}")]
+    StateFile {
+        path: String,
+        #[source]
+        error: std::io::Error,
+    },
+
+    #[error("no job named `{0}` configured")]
+    UnknownJob(String),
+
+    #[error("multiple jobs configured, select one with --job")]
+    JobSelectionRequired,
+
+    #[error("docker error: {0}")]
+    Docker(#[source] DockerError),
+
+    #[error("acme error: {0}")]
+    Acme(#[source] AcmeError),
+
+    #[error("ports {ports:?} do not serve the installed certificate")]
+    TlsVerificationFailed { ports: Vec<u16> },
+
+    #[error("certificates are already up to date")]
+    NoChanges,
+
+    /// `exit_code` is the exit code of the first failed job.
+    #[error("jobs failed: {}", jobs.join(", "))]
+    JobsFailed { jobs: Vec<String>, exit_code: u8 },
+
+    #[error("could not read answer")]
+    Prompt(#[source] std::io::Error),
+
     #[error("loki logging configuration error")]
     LokiConfigurationError(#[source] anyhow::Error),
 
@@ -46,6 +175,92 @@ pub enum MoorenewError {
     Unknown(#[source] anyhow::Error),
 }
 
+impl MoorenewError {
+    /// Returns the process exit code for the error, so scripts and monitoring can tell the
+    /// failure classes apart.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            MoorenewError::NoChanges => EXIT_UP_TO_DATE,
+            MoorenewError::ConfigurationError(_)
+            | MoorenewError::UnknownJob(_)
+            | MoorenewError::JobSelectionRequired
+            | MoorenewError::UnknownServiceProvider(_)
+            | MoorenewError::LokiConfigurationError(_) => EXIT_CONFIGURATION,
+            MoorenewError::SSHConnectError(_)
+            | MoorenewError::SshSession(_)
+            | MoorenewError::SshHandshake(_)
+            | MoorenewError::SshTunnel { .. }
+            | MoorenewError::SshNotConnected(_) => EXIT_SSH_CONNECTION,
+            MoorenewError::SshAuthentication { .. } => EXIT_SSH_AUTHENTICATION,
+            MoorenewError::HostKeyMismatch { .. }
+            | MoorenewError::HostKeyUnknown { .. }
+            | MoorenewError::HostKeyVerification(_)
+            | MoorenewError::KnownHostsUpdate(_) => EXIT_HOST_KEY,
+            MoorenewError::SSHExecutionError(_)
+            | MoorenewError::CalculatingChecksum(_)
+            | MoorenewError::FileTransfer(_)
+            | MoorenewError::Sftp(_)
+            | MoorenewError::SftpOpen { .. }
+            | MoorenewError::CertificateSource { .. }
+            | MoorenewError::Acme(_)
+            | MoorenewError::RemoteCommandFailed { .. }
+            | MoorenewError::Utf8Output { .. } => EXIT_TRANSFER,
+            MoorenewError::CertificateValidation(_) | MoorenewError::CertificateNotFound { .. } => {
+                EXIT_CERTIFICATE_VALIDATION
+            }
+            MoorenewError::CertificateInstallation(_)
+            | MoorenewError::CertificateBackup(_)
+            | MoorenewError::NoBackupAvailable => EXIT_INSTALLATION,
+            MoorenewError::Docker(_) => EXIT_CONTAINERS_FAILED,
+            MoorenewError::TlsVerificationFailed { .. } => EXIT_TLS_VERIFICATION,
+            MoorenewError::JobsFailed { exit_code, .. } => *exit_code,
+            _ => EXIT_FAILURE,
+        }
+    }
+
+    /// Whether the error is a connection failure which may go away on its own, so the download is
+    /// worth retrying. Missing files, permissions, authentication, host key and validation errors
+    /// are not.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            // the tunnel fails if the jump host can not reach the sftp host
+            MoorenewError::SSHConnectError(_) | MoorenewError::SshTunnel { .. } => true,
+            MoorenewError::SshHandshake(error)
+            | MoorenewError::SSHExecutionError(error)
+            | MoorenewError::Sftp(error)
+            | MoorenewError::SftpOpen { error, .. } => is_connection_error(error),
+            MoorenewError::FileTransfer(error) | MoorenewError::CertificateSource { error, .. } => {
+                is_connection_io_error(error)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn is_connection_error(error: &ssh2::Error) -> bool {
+    matches!(error.code(), ssh2::ErrorCode::Session(code) if SSH_CONNECTION_ERRORS.contains(&code))
+}
+
+fn is_connection_io_error(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        std::io::ErrorKind::TimedOut
+            | std::io::ErrorKind::ConnectionRefused
+            | std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::NotConnected
+            | std::io::ErrorKind::BrokenPipe
+            | std::io::ErrorKind::UnexpectedEof
+            | std::io::ErrorKind::HostUnreachable
+            | std::io::ErrorKind::NetworkUnreachable
+    )
+}
+
+/// libssh2 error codes of a broken or timed out connection: `LIBSSH2_ERROR_SOCKET_SEND`,
+/// `LIBSSH2_ERROR_TIMEOUT`, `LIBSSH2_ERROR_SOCKET_DISCONNECT`, `LIBSSH2_ERROR_SOCKET_TIMEOUT` and
+/// `LIBSSH2_ERROR_SOCKET_RECV`.
+const SSH_CONNECTION_ERRORS: &[i32] = &[-7, -9, -13, -30, -43];
+
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum ConfigurationError {
@@ -66,4 +281,249 @@ pub enum ConfigurationError {
 
     #[error("could not determine home directory")]
     HomeDirUnavailable,
+
+    #[error("no jobs configured")]
+    NoJobsConfigured,
+
+    #[error("no reload command configured for container `{0}`")]
+    ReloadCommandMissing(String),
+
+    #[error("invalid certificate source url: {0}")]
+    InvalidSourceUrl(String),
+
+    #[error("invalid jump host `{0}`, expected [user@]host[:port]")]
+    InvalidJumpHost(String),
+
+    #[error("could not read ssh config {path}")]
+    SshConfig {
+        path: String,
+        #[source]
+        error: std::io::Error,
+    },
+
+    #[error("invalid domain `{0}`, it has to be a plain directory name")]
+    InvalidDomain(String),
+
+    #[error("invalid tsig key: {0}")]
+    InvalidTsigKey(String),
+
+    #[error("no configuration file at {0}, create one with `moorenew init`")]
+    ConfigFileMissing(String),
+
+    #[error("configuration file {0} already exists, run with --force to overwrite it")]
+    ConfigFileExists(String),
+
+    #[error("no value for `{0}`, pass it as argument")]
+    InitValueMissing(String),
+
+    #[error("could not read {path}")]
+    MailcowConf {
+        path: String,
+        #[source]
+        error: std::io::Error,
+    },
+
+    #[error("configuration has {problems} problem(s)")]
+    ValidationFailed { problems: usize },
+
+    #[error("invalid configuration override `{variable}`: {reason}")]
+    InvalidOverride { variable: String, reason: String },
+
+    #[error("could not read `{key}` from {path}")]
+    SecretFile {
+        key: String,
+        path: String,
+        #[source]
+        error: std::io::Error,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum CertificateValidationError {
+    #[error("could not parse certificate chain")]
+    ChainParsing(#[source] openssl::error::ErrorStack),
+
+    #[error("certificate chain is empty")]
+    EmptyChain,
+
+    #[error("could not parse private key")]
+    PrivateKeyParsing(#[source] openssl::error::ErrorStack),
+
+    #[error("private key does not match the certificate")]
+    KeyMismatch,
+
+    #[error("certificate {index} of the chain is not issued by the certificate following it")]
+    ChainOrder { index: usize },
+
+    #[error("certificate chain is incomplete, the issuer of `{subject}` is missing")]
+    ChainIncomplete { subject: String },
+
+    #[error("certificate is not valid before {not_before}")]
+    NotYetValid { not_before: String },
+
+    #[error("certificate expired on {not_after}")]
+    Expired { not_after: String },
+
+    #[error("certificate does not cover {}", hostnames.join(", "))]
+    HostnamesNotCovered { hostnames: Vec<String> },
+
+    #[error("openssl error")]
+    OpenSsl(#[source] openssl::error::ErrorStack),
+}
+
+#[derive(Debug, Error)]
+pub enum DockerError {
+    #[error("could not talk to the docker socket")]
+    Connection(#[source] std::io::Error),
+
+    #[error("docker api returned status {status}: {message}")]
+    Api { status: u16, message: String },
+
+    #[error("could not parse docker api response")]
+    Json(#[source] serde_json::Error),
+
+    #[error("no container named `{0}` found")]
+    ContainerNotFound(String),
+
+    #[error("multiple containers belong to the compose service `{0}`")]
+    AmbiguousContainer(String),
+
+    #[error("`{command}` in container `{name}` exited with {exit_code}: {output}")]
+    ExecFailed {
+        name: String,
+        command: String,
+        exit_code: i64,
+        output: String,
+    },
+
+    #[error("container `{name}` did not become ready, last state: {state}")]
+    NotReady { name: String, state: String },
+}
+
+#[derive(Debug, Error)]
+pub enum AcmeError {
+    #[error("could not reach the acme server")]
+    Connection(#[source] std::io::Error),
+
+    #[error("acme server returned status {status} ({problem_type}): {detail}")]
+    Problem {
+        status: u16,
+        problem_type: String,
+        detail: String,
+    },
+
+    #[error("could not parse acme response")]
+    Json(#[source] serde_json::Error),
+
+    #[error("acme response is missing the {0} header")]
+    MissingHeader(&'static str),
+
+    #[error("no {challenge} challenge offered for {identifier}")]
+    ChallengeUnsupported {
+        identifier: String,
+        challenge: String,
+    },
+
+    #[error("authorization of {identifier} failed: {detail}")]
+    AuthorizationFailed { identifier: String, detail: String },
+
+    #[error("order failed with status {0}")]
+    OrderFailed(String),
+
+    #[error("timed out waiting for {0}")]
+    Timeout(String),
+
+    #[error("could not read or write the account key {path}")]
+    AccountKey {
+        path: String,
+        #[source]
+        error: std::io::Error,
+    },
+
+    #[error("could not provide the challenge response")]
+    Challenge(#[source] std::io::Error),
+
+    #[error("dns update of {record} failed with rcode {rcode}")]
+    DnsUpdate { record: String, rcode: u8 },
+
+    #[error("openssl error")]
+    OpenSsl(#[source] openssl::error::ErrorStack),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CertificateValidationError, EXIT_CERTIFICATE_VALIDATION, EXIT_HOST_KEY, EXIT_UP_TO_DATE,
+        MoorenewError,
+    };
+    use std::io::{Error, ErrorKind};
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(MoorenewError::NoChanges.exit_code(), EXIT_UP_TO_DATE);
+        assert_eq!(
+            MoorenewError::HostKeyUnknown {
+                host: "example.com".to_string(),
+                fingerprint: "SHA256:abc".to_string(),
+            }
+            .exit_code(),
+            EXIT_HOST_KEY
+        );
+        assert_eq!(
+            MoorenewError::JobsFailed {
+                jobs: vec!["default".to_string()],
+                exit_code: EXIT_CERTIFICATE_VALIDATION,
+            }
+            .exit_code(),
+            EXIT_CERTIFICATE_VALIDATION
+        );
+        assert_eq!(
+            MoorenewError::CertificateValidation(CertificateValidationError::KeyMismatch)
+                .exit_code(),
+            EXIT_CERTIFICATE_VALIDATION
+        );
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(MoorenewError::SSHConnectError(Error::from(ErrorKind::TimedOut)).is_transient());
+        assert!(
+            MoorenewError::SftpOpen {
+                path: "/etc/letsencrypt/live/npm-1/fullchain.pem".to_string(),
+                error: ssh2::Error::new(ssh2::ErrorCode::Session(-9), "timed out"),
+            }
+            .is_transient()
+        );
+        assert!(
+            !MoorenewError::SftpOpen {
+                path: "/etc/letsencrypt/live/npm-1/fullchain.pem".to_string(),
+                error: ssh2::Error::new(ssh2::ErrorCode::SFTP(2), "no such file"),
+            }
+            .is_transient()
+        );
+        assert!(
+            !MoorenewError::CertificateSource {
+                location: "/etc/letsencrypt/live/mail.example.com".to_string(),
+                error: Error::from(ErrorKind::NotFound),
+            }
+            .is_transient()
+        );
+        assert!(
+            !MoorenewError::FileTransfer(Error::from(ErrorKind::PermissionDenied)).is_transient()
+        );
+        assert!(
+            !MoorenewError::Sftp(ssh2::Error::new(
+                ssh2::ErrorCode::SFTP(3),
+                "permission denied"
+            ))
+            .is_transient()
+        );
+        assert!(!MoorenewError::CalculatingChecksum(Error::other("no checksum")).is_transient());
+        assert!(
+            !MoorenewError::SshAuthentication {
+                attempts: Vec::new()
+            }
+            .is_transient()
+        );
+    }
 }
@@ -0,0 +1,240 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::utils::certvalidation::{days_until_expiry, leaf_certificate};
+use crate::utils::errors::MoorenewError;
+
+/// File in the state directory remembering the thresholds which were already alerted.
+const ALERTS_FILE: &str = "expiry.json";
+
+/// Name of the certificate in `mail_cert_path`, SNI certificates are named after their domain.
+pub const MAIN_CERTIFICATE: &str = "main";
+
+/// Expiry of a certificate of a job, both installed and at its source.
+#[derive(Debug, Clone)]
+pub struct CertificateExpiry {
+    /// [`MAIN_CERTIFICATE`] or the SNI domain.
+    pub certificate: String,
+    /// `None` if no certificate is installed yet.
+    pub installed: Option<Expiry>,
+    /// `None` if the source could not be read or has no certificate of its own, like ACME.
+    pub source: Option<Expiry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expiry {
+    pub not_after: String,
+    /// Whole days until the certificate expires, negative if it already expired.
+    pub days: i32,
+}
+
+impl Expiry {
+    pub fn from_fullchain(fullchain: &[u8]) -> Result<Expiry, MoorenewError> {
+        Ok(Expiry {
+            not_after: leaf_certificate(fullchain)?.not_after,
+            days: days_until_expiry(fullchain)?,
+        })
+    }
+}
+
+/// The lowest threshold already alerted for a certificate.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct AlertedThreshold {
+    not_after: String,
+    threshold: u32,
+}
+
+/// Thresholds already alerted per certificate, so every threshold is only alerted once even though
+/// the expiry is checked on every run. Renewed certificates start over.
+#[derive(Debug, Default)]
+pub struct ExpiryAlerts {
+    path: PathBuf,
+    alerted: BTreeMap<String, AlertedThreshold>,
+}
+
+impl ExpiryAlerts {
+    /// Reads the alerted thresholds from `state_dir`. A missing or unreadable file starts over.
+    pub fn load(state_dir: &Path) -> ExpiryAlerts {
+        let path = state_dir.join(ALERTS_FILE);
+        let alerted = match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                warn!(error = %e, path = %path.display(), "ignoring unreadable expiry alerts");
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+
+        ExpiryAlerts { path, alerted }
+    }
+
+    pub fn save(&self) -> Result<(), MoorenewError> {
+        let state_error = |error| MoorenewError::StateFile {
+            path: self.path.display().to_string(),
+            error,
+        };
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).map_err(state_error)?;
+        }
+        let contents = serde_json::to_vec_pretty(&self.alerted)
+            .map_err(|e| state_error(std::io::Error::other(e)))?;
+        let temp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&temp_path, contents)
+            .and_then(|_| std::fs::rename(&temp_path, &self.path))
+            .map_err(state_error)
+    }
+
+    /// Returns the lowest of `thresholds` the certificate `key` reached, unless it was already
+    /// alerted for the same certificate.
+    pub fn crossed_threshold(
+        &mut self,
+        key: &str,
+        expiry: &Expiry,
+        thresholds: &[u32],
+    ) -> Option<u32> {
+        let threshold = thresholds
+            .iter()
+            .copied()
+            .filter(|threshold| expiry.days <= *threshold as i32)
+            .min()?;
+
+        if let Some(alerted) = self.alerted.get(key)
+            && alerted.not_after == expiry.not_after
+            && alerted.threshold <= threshold
+        {
+            return None;
+        }
+
+        self.alerted.insert(
+            key.to_string(),
+            AlertedThreshold {
+                not_after: expiry.not_after.clone(),
+                threshold,
+            },
+        );
+        Some(threshold)
+    }
+}
+
+/// Returns the warnings for the certificates of `job` which newly reached one of `thresholds`.
+pub fn expiry_warnings(
+    job: &str,
+    expiries: &[CertificateExpiry],
+    thresholds: &[u32],
+    alerts: &mut ExpiryAlerts,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    for expiry in expiries {
+        if let Some(installed) = &expiry.installed
+            && alerts
+                .crossed_threshold(
+                    &format!("{job}/{}/installed", expiry.certificate),
+                    installed,
+                    thresholds,
+                )
+                .is_some()
+        {
+            warnings.push(format!(
+                "{job}: installed {} certificate {}",
+                expiry.certificate,
+                describe(installed)
+            ));
+        }
+
+        if let Some(source) = &expiry.source
+            && alerts
+                .crossed_threshold(
+                    &format!("{job}/{}/source", expiry.certificate),
+                    source,
+                    thresholds,
+                )
+                .is_some()
+        {
+            warnings.push(format!(
+                "{job}: {} certificate at the source {}, check that the source still renews it",
+                expiry.certificate,
+                describe(source)
+            ));
+        }
+    }
+
+    warnings
+}
+
+fn describe(expiry: &Expiry) -> String {
+    if expiry.days < 0 {
+        format!("expired {} days ago ({})", -expiry.days, expiry.not_after)
+    } else {
+        format!("expires in {} days ({})", expiry.days, expiry.not_after)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CertificateExpiry, Expiry, ExpiryAlerts, expiry_warnings};
+    use crate::utils::testutil::TempDir;
+
+    fn expiry(days: i32, not_after: &str) -> Expiry {
+        Expiry {
+            not_after: not_after.to_string(),
+            days,
+        }
+    }
+
+    #[test]
+    fn test_crossed_threshold() {
+        let mut alerts = ExpiryAlerts::default();
+        let thresholds = [21, 7, 1];
+
+        assert_eq!(
+            alerts.crossed_threshold("main", &expiry(30, "a"), &thresholds),
+            None
+        );
+        assert_eq!(
+            alerts.crossed_threshold("main", &expiry(20, "a"), &thresholds),
+            Some(21)
+        );
+        assert_eq!(
+            alerts.crossed_threshold("main", &expiry(19, "a"), &thresholds),
+            None
+        );
+        assert_eq!(
+            alerts.crossed_threshold("main", &expiry(6, "a"), &thresholds),
+            Some(7)
+        );
+        assert_eq!(
+            alerts.crossed_threshold("main", &expiry(-2, "a"), &thresholds),
+            Some(1)
+        );
+        // a renewed certificate starts over
+        assert_eq!(
+            alerts.crossed_threshold("main", &expiry(20, "b"), &thresholds),
+            Some(21)
+        );
+        assert_eq!(alerts.crossed_threshold("main", &expiry(5, "b"), &[]), None);
+    }
+
+    #[test]
+    fn test_expiry_warnings() {
+        let state_dir = TempDir::new("expiry");
+        let expiries = vec![CertificateExpiry {
+            certificate: "main".to_string(),
+            installed: Some(expiry(5, "Jan  1 00:00:00 2030 GMT")),
+            source: Some(expiry(60, "Mar  1 00:00:00 2030 GMT")),
+        }];
+
+        let mut alerts = ExpiryAlerts::load(&state_dir);
+        assert_eq!(
+            expiry_warnings("example.com", &expiries, &[21, 7, 1], &mut alerts),
+            vec![
+                "example.com: installed main certificate expires in 5 days (Jan  1 00:00:00 2030 GMT)"
+            ]
+        );
+        alerts.save().unwrap();
+
+        let mut alerts = ExpiryAlerts::load(&state_dir);
+        assert!(expiry_warnings("example.com", &expiries, &[21, 7, 1], &mut alerts).is_empty());
+    }
+}
@@ -0,0 +1,394 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::system::sysinfo::get_loggedin_user;
+use crate::utils::certvalidation::CertificateInfo;
+use crate::utils::errors::MoorenewError;
+
+/// Append-only file in the state directory with one JSON entry per line.
+const HISTORY_FILE: &str = "history.jsonl";
+
+/// Tracing target of the audit events, so they can be filtered and shipped separately. The field
+/// names of these events are stable.
+pub const AUDIT_TARGET: &str = "moorenew::audit";
+
+/// A run which changed certificates or failed, or a rollback.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// RFC 3339 time the run started.
+    pub time: String,
+    pub job: String,
+    /// User moorenew ran as.
+    pub user: String,
+    /// `success`, `partially successful`, `failed` or `rolled back`.
+    pub result: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub certificates: Vec<CertificateChange>,
+    #[serde(default)]
+    pub containers: Vec<ContainerResult>,
+}
+
+/// An installed certificate which got replaced.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CertificateChange {
+    /// [`MAIN_CERTIFICATE`] or the SNI domain.
+    ///
+    /// [`MAIN_CERTIFICATE`]: crate::utils::expiry::MAIN_CERTIFICATE
+    pub certificate: String,
+    /// SSH host the certificate was fetched from, `None` for sources which don't use SSH.
+    pub source_host: Option<String>,
+    /// Location of the certificate at its source, or the backup it was restored from.
+    pub source: String,
+    /// `None` if no certificate was installed before.
+    pub previous: Option<CertificateRecord>,
+    pub new: CertificateRecord,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CertificateRecord {
+    pub fingerprint: String,
+    pub serial: String,
+    pub subject: String,
+    pub not_before: String,
+    pub not_after: String,
+}
+
+impl From<&CertificateInfo> for CertificateRecord {
+    fn from(info: &CertificateInfo) -> CertificateRecord {
+        CertificateRecord {
+            fingerprint: info.fingerprint.clone(),
+            serial: info.serial.clone(),
+            subject: info.subject.clone(),
+            not_before: info.not_before.clone(),
+            not_after: info.not_after.clone(),
+        }
+    }
+}
+
+/// What happened to a container after the certificates changed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ContainerResult {
+    pub name: String,
+    /// `reloaded`, `restarted` or `failed`.
+    pub result: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ContainerResult {
+    pub fn failed(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+impl HistoryEntry {
+    /// Creates an entry for a run of `job` starting now.
+    pub fn new(job: &str) -> HistoryEntry {
+        HistoryEntry {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            job: job.to_string(),
+            user: get_loggedin_user().unwrap_or_else(|_| "unknown".to_string()),
+            result: String::new(),
+            error: None,
+            certificates: Vec::new(),
+            containers: Vec::new(),
+        }
+    }
+
+    /// Emits the entry as events of [`AUDIT_TARGET`]: one `certificate_replaced` event per
+    /// certificate, one `container_action` event per container and a final `run` event.
+    pub fn emit_audit_events(&self) {
+        for change in &self.certificates {
+            let previous = change.previous.as_ref();
+            info!(
+                target: AUDIT_TARGET,
+                audit_event = "certificate_replaced",
+                job = self.job,
+                user = self.user,
+                certificate = change.certificate,
+                source_host = change.source_host,
+                source = change.source,
+                old_fingerprint = previous.map(|previous| previous.fingerprint.as_str()),
+                old_serial = previous.map(|previous| previous.serial.as_str()),
+                old_not_after = previous.map(|previous| previous.not_after.as_str()),
+                new_fingerprint = change.new.fingerprint,
+                new_serial = change.new.serial,
+                new_subject = change.new.subject,
+                new_not_before = change.new.not_before,
+                new_not_after = change.new.not_after,
+                "certificate replaced"
+            );
+        }
+        for container in &self.containers {
+            info!(
+                target: AUDIT_TARGET,
+                audit_event = "container_action",
+                job = self.job,
+                user = self.user,
+                container = container.name,
+                result = container.result,
+                error = container.error,
+                "container action"
+            );
+        }
+        info!(
+            target: AUDIT_TARGET,
+            audit_event = "run",
+            job = self.job,
+            user = self.user,
+            result = self.result,
+            error = self.error,
+            certificates = self.certificates.len(),
+            "run recorded"
+        );
+    }
+}
+
+pub fn history_path(state_dir: &Path) -> PathBuf {
+    state_dir.join(HISTORY_FILE)
+}
+
+/// Appends `entry` to the history in `state_dir`.
+pub fn append(state_dir: &Path, entry: &HistoryEntry) -> Result<(), MoorenewError> {
+    let path = history_path(state_dir);
+    let mut line =
+        serde_json::to_vec(entry).map_err(|e| history_error(&path, std::io::Error::other(e)))?;
+    line.push(b'\n');
+
+    std::fs::create_dir_all(state_dir)
+        .and_then(|_| {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+        })
+        // a single write, so concurrent runs never interleave their lines
+        .and_then(|mut file| file.write_all(&line))
+        .map_err(|e| history_error(&path, e))
+}
+
+/// Reads the history in `state_dir`, oldest entry first. Lines which cannot be parsed are logged
+/// and skipped.
+pub fn load(state_dir: &Path) -> Result<Vec<HistoryEntry>, MoorenewError> {
+    let path = history_path(state_dir);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(history_error(&path, e)),
+    };
+
+    Ok(contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(index, line)| {
+            serde_json::from_str(line)
+                .inspect_err(
+                    |e| warn!(error = %e, line = index + 1, "skipping unreadable history entry"),
+                )
+                .ok()
+        })
+        .collect())
+}
+
+/// Selects entries of the history for `moorenew history`.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryFilter {
+    pub job: Option<String>,
+    /// Only entries at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Prefix of the fingerprint of a replaced or a new certificate.
+    pub fingerprint: Option<String>,
+    /// Only the last `limit` matching entries.
+    pub limit: Option<usize>,
+}
+
+impl HistoryFilter {
+    pub fn apply(&self, entries: Vec<HistoryEntry>) -> Vec<HistoryEntry> {
+        let mut entries: Vec<HistoryEntry> = entries
+            .into_iter()
+            .filter(|entry| self.matches(entry))
+            .collect();
+        if let Some(limit) = self.limit {
+            entries.drain(..entries.len().saturating_sub(limit));
+        }
+        entries
+    }
+
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        if self.job.as_ref().is_some_and(|job| *job != entry.job) {
+            return false;
+        }
+        if let Some(since) = self.since
+            && DateTime::parse_from_rfc3339(&entry.time).map_or(true, |time| time < since)
+        {
+            return false;
+        }
+        if let Some(fingerprint) = &self.fingerprint {
+            let fingerprint = fingerprint.to_lowercase().replace(':', "");
+            return entry.certificates.iter().any(|change| {
+                change
+                    .previous
+                    .iter()
+                    .chain([&change.new])
+                    .any(|record| record.fingerprint.starts_with(&fingerprint))
+            });
+        }
+        true
+    }
+}
+
+/// Formats `entries` with one block per entry, listing the replaced certificates and the
+/// containers below the run.
+pub fn format_history(entries: &[HistoryEntry]) -> String {
+    let mut lines = Vec::new();
+    for entry in entries {
+        lines.push(format!(
+            "{}  {}  {}  by {}",
+            entry.time, entry.job, entry.result, entry.user
+        ));
+        for change in &entry.certificates {
+            let previous = change
+                .previous
+                .as_ref()
+                .map(|previous| short_fingerprint(&previous.fingerprint))
+                .unwrap_or("none");
+            let source = match &change.source_host {
+                Some(host) => format!("{host}:{}", change.source),
+                None => change.source.clone(),
+            };
+            lines.push(format!(
+                "  {}: {previous} -> {} (serial {}, valid until {}) from {source}",
+                change.certificate,
+                short_fingerprint(&change.new.fingerprint),
+                change.new.serial,
+                change.new.not_after,
+            ));
+        }
+        for container in &entry.containers {
+            match &container.error {
+                Some(error) => lines.push(format!(
+                    "  {}: {} ({error})",
+                    container.name, container.result
+                )),
+                None => lines.push(format!("  {}: {}", container.name, container.result)),
+            }
+        }
+        if let Some(error) = &entry.error {
+            lines.push(format!("  error: {error}"));
+        }
+    }
+    lines.join("\n")
+}
+
+fn short_fingerprint(fingerprint: &str) -> &str {
+    fingerprint.get(..16).unwrap_or(fingerprint)
+}
+
+fn history_error(path: &Path, error: std::io::Error) -> MoorenewError {
+    MoorenewError::StateFile {
+        path: path.display().to_string(),
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CertificateChange, CertificateRecord, ContainerResult, HistoryEntry, HistoryFilter, append,
+        format_history, load,
+    };
+    use crate::utils::testutil::TempDir;
+    use chrono::{TimeZone, Utc};
+
+    fn record(fingerprint: &str) -> CertificateRecord {
+        CertificateRecord {
+            fingerprint: fingerprint.repeat(32),
+            serial: "3a".to_string(),
+            subject: "CN=mail.example.com".to_string(),
+            not_before: "Oct  1 00:00:00 2026 GMT".to_string(),
+            not_after: "Dec 30 00:00:00 2026 GMT".to_string(),
+        }
+    }
+
+    fn entry(time: &str, job: &str, previous: &str, new: &str) -> HistoryEntry {
+        HistoryEntry {
+            time: time.to_string(),
+            job: job.to_string(),
+            user: "root".to_string(),
+            result: "success".to_string(),
+            error: None,
+            certificates: vec![CertificateChange {
+                certificate: "main".to_string(),
+                source_host: Some("nas.example.com".to_string()),
+                source: "/certs/npm-1".to_string(),
+                previous: Some(record(previous)),
+                new: record(new),
+            }],
+            containers: vec![ContainerResult {
+                name: "postfix-mailcow".to_string(),
+                result: "restarted".to_string(),
+                error: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let state_dir = TempDir::new("history");
+        let entries = vec![
+            entry("2026-09-01T12:00:00Z", "example.com", "aa", "bb"),
+            entry("2026-09-02T12:00:00Z", "example.org", "cc", "dd"),
+            entry("2026-10-01T12:00:00Z", "example.com", "bb", "ee"),
+        ];
+        for entry in &entries {
+            append(&state_dir, entry).unwrap();
+        }
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(state_dir.join("history.jsonl"))
+            .and_then(|mut file| std::io::Write::write_all(&mut file, b"{truncated\n"))
+            .unwrap();
+        assert_eq!(load(&state_dir).unwrap(), entries);
+
+        let filter = |filter: HistoryFilter| -> Vec<String> {
+            filter
+                .apply(entries.clone())
+                .into_iter()
+                .map(|entry| entry.time)
+                .collect()
+        };
+        assert_eq!(
+            filter(HistoryFilter {
+                job: Some("example.com".to_string()),
+                ..Default::default()
+            }),
+            ["2026-09-01T12:00:00Z", "2026-10-01T12:00:00Z"]
+        );
+        assert_eq!(
+            filter(HistoryFilter {
+                since: Some(Utc.with_ymd_and_hms(2026, 9, 2, 0, 0, 0).unwrap()),
+                limit: Some(1),
+                ..Default::default()
+            }),
+            ["2026-10-01T12:00:00Z"]
+        );
+        assert_eq!(
+            filter(HistoryFilter {
+                fingerprint: Some("BB:BB".to_string()),
+                ..Default::default()
+            }),
+            ["2026-09-01T12:00:00Z", "2026-10-01T12:00:00Z"]
+        );
+
+        assert_eq!(
+            format_history(&entries[..1]),
+            "2026-09-01T12:00:00Z  example.com  success  by root\n  main: aaaaaaaaaaaaaaaa -> bbbbbbbbbbbbbbbb (serial 3a, valid until Dec 30 00:00:00 2026 GMT) from nas.example.com:/certs/npm-1\n  postfix-mailcow: restarted"
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use openssl::ssl::{SslConnector, SslMethod};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use url::Url;
+
+/// Read and write timeout of [`https_request`].
+const HTTPS_TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest response body read, so a broken or malicious server can not exhaust the memory.
+const MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+/// Response of a HTTP/1.1 request. Chunked bodies are already decoded.
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// request sends a single HTTP/1.1 request over `stream` and reads the response. The connection
+/// is closed by the server afterwards, so every request needs a new stream. This is enough for
+/// talking to the Docker Engine API and other simple JSON APIs without pulling in a full HTTP
+/// client.
+pub fn request<S: Read + Write>(
+    stream: S,
+    method: &str,
+    host: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: Option<&[u8]>,
+) -> std::io::Result<HttpResponse> {
+    let mut reader = BufReader::new(stream);
+
+    let mut head = format!("{method} {path} HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n");
+    for (key, value) in headers {
+        head.push_str(&format!("{key}: {value}\r\n"));
+    }
+    if let Some(body) = body {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("\r\n");
+
+    let mut message = head.into_bytes();
+    if let Some(body) = body {
+        message.extend_from_slice(body);
+    }
+    let stream = reader.get_mut();
+    stream.write_all(&message)?;
+    stream.flush()?;
+
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| invalid_data(format!("invalid status line `{}`", status_line.trim())))?;
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid_data("connection closed while reading headers"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            headers.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let mut response = HttpResponse {
+        status,
+        headers,
+        body: Vec::new(),
+    };
+
+    if method == "HEAD" || status == 204 || status == 304 {
+        return Ok(response);
+    }
+
+    if response
+        .header("Transfer-Encoding")
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"))
+    {
+        response.body = read_chunked(&mut reader)?;
+    } else if let Some(length) = response.header("Content-Length") {
+        let length = length
+            .parse::<usize>()
+            .map_err(|_| invalid_data(format!("invalid content length `{length}`")))?;
+        read_limited(&mut reader, length, &mut response.body)?;
+    } else {
+        reader
+            .by_ref()
+            .take(MAX_BODY_SIZE as u64 + 1)
+            .read_to_end(&mut response.body)?;
+        if response.body.len() > MAX_BODY_SIZE {
+            return Err(body_too_large());
+        }
+    }
+
+    Ok(response)
+}
+
+/// https_request sends a single request over a new TLS connection to the host of `url`. The
+/// server certificate is verified against the system trust store, or against the CA certificates
+/// in `ca_file` if set. Connecting fails after `connect_timeout`.
+pub fn https_request(
+    url: &Url,
+    method: &str,
+    headers: &[(&str, &str)],
+    body: Option<&[u8]>,
+    ca_file: Option<&str>,
+    connect_timeout: Duration,
+) -> std::io::Result<HttpResponse> {
+    if url.scheme() != "https" {
+        return Err(Error::other(format!(
+            "unsupported scheme `{}`, use https",
+            url.scheme()
+        )));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| Error::other("url has no host"))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let stream = connect(host, port, connect_timeout)?;
+    stream.set_read_timeout(Some(HTTPS_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTPS_TIMEOUT))?;
+
+    let mut connector = SslConnector::builder(SslMethod::tls_client()).map_err(Error::other)?;
+    if let Some(ca_file) = ca_file {
+        connector.set_ca_file(ca_file).map_err(Error::other)?;
+    }
+    let stream = connector
+        .build()
+        .connect(host, stream)
+        .map_err(Error::other)?;
+
+    let host_header = match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    };
+    let path = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    };
+    request(stream, method, &host_header, &path, headers, body)
+}
+
+/// Connects to the first address of `host` which accepts the connection within `timeout`.
+fn connect(host: &str, port: u16, timeout: Duration) -> std::io::Result<TcpStream> {
+    let mut last_error = Error::new(ErrorKind::NotFound, format!("{host} has no address"));
+    for address in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+fn read_chunked<R: BufRead>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let mut size_line = String::new();
+        if reader.read_line(&mut size_line)? == 0 {
+            return Err(invalid_data("connection closed while reading chunk"));
+        }
+        let size = size_line.trim().split(';').next().unwrap_or_default();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| invalid_data(format!("invalid chunk size `{}`", size_line.trim())))?;
+
+        if size == 0 {
+            // skip trailers
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                    return Ok(body);
+                }
+            }
+        }
+
+        read_limited(reader, size, &mut body)?;
+
+        let mut line_end = String::new();
+        reader.read_line(&mut line_end)?;
+    }
+}
+
+/// Appends the next `length` bytes of `reader` to `body`, without trusting `length` before the
+/// bytes actually arrived.
+fn read_limited<R: Read>(reader: &mut R, length: usize, body: &mut Vec<u8>) -> std::io::Result<()> {
+    if body.len().saturating_add(length) > MAX_BODY_SIZE {
+        return Err(body_too_large());
+    }
+    let read = reader.take(length as u64).read_to_end(body)?;
+    if read < length {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "connection closed while reading body",
+        ));
+    }
+    Ok(())
+}
+
+fn body_too_large() -> Error {
+    invalid_data(format!("response body exceeds {MAX_BODY_SIZE} bytes"))
+}
+
+fn invalid_data(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_BODY_SIZE, request};
+    use std::io::{Cursor, Read, Write};
+
+    /// Stream returning a canned response and recording the request.
+    struct MockStream {
+        request: Vec<u8>,
+        response: Cursor<Vec<u8>>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.response.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.request.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_request_chunked_response() {
+        let mut stream = MockStream {
+            request: Vec::new(),
+            response: Cursor::new(
+                b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n[1,2,\r\n2\r\n3]\r\n0\r\n\r\n"
+                    .to_vec(),
+            ),
+        };
+
+        let response =
+            request(&mut stream, "GET", "docker", "/containers/json", &[], None).unwrap();
+
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("content-type"), Some("application/json"));
+        assert_eq!(response.body, b"[1,2,3]");
+        assert!(
+            String::from_utf8(stream.request)
+                .unwrap()
+                .starts_with("GET /containers/json HTTP/1.1\r\nHost: docker\r\n")
+        );
+    }
+
+    #[test]
+    fn test_request_body_too_large() {
+        for response in [
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{{}}",
+                usize::MAX
+            ),
+            format!(
+                "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n",
+                MAX_BODY_SIZE + 1
+            ),
+        ] {
+            let stream = MockStream {
+                request: Vec::new(),
+                response: Cursor::new(response.into_bytes()),
+            };
+            let error = request(stream, "GET", "docker", "/", &[], None).unwrap_err();
+            assert!(error.to_string().contains("exceeds"), "{error}");
+        }
+
+        let stream = MockStream {
+            request: Vec::new(),
+            response: Cursor::new(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n{}".to_vec()),
+        };
+        assert!(request(stream, "GET", "docker", "/", &[], None).is_err());
+    }
+}
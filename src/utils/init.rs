@@ -0,0 +1,374 @@
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
+
+use crate::system::sysinfo;
+use crate::utils::certvalidation::validate_certificate_pair;
+use crate::utils::configuration::{Configuration, JobConfiguration, validate_configuration};
+use crate::utils::errors::{ConfigurationError, MoorenewError};
+use crate::utils::mailcow::{self, DEFAULT_MAILCOW_DIR};
+use crate::utils::ssh::{HostKeyVerification, SSHClient, SshAuthentication};
+use crate::utils::sshconfig::resolve_route;
+use crate::utils::sshkeygen;
+
+const DEFAULT_SFTP_USER: &str = "mailcow";
+const DEFAULT_NPM_CERT_PATH: &str = "/etc/letsencrypt/live/npm-1";
+
+/// Values passed on the command line. Every value which is set is not asked for.
+#[derive(Debug, Default, Clone)]
+pub struct InitOptions {
+    /// Use the passed or default values without asking, for scripted installs.
+    pub defaults: bool,
+    /// Overwrite an existing configuration file.
+    pub force: bool,
+    pub sftp_host: Option<String>,
+    pub sftp_port: Option<u16>,
+    pub sftp_user: Option<String>,
+    pub npm_cert_path: Option<String>,
+    pub mailcow_dir: Option<String>,
+    pub private_key_path: Option<String>,
+}
+
+/// Answers of the setup questions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitAnswers {
+    pub sftp_host: String,
+    pub sftp_port: u16,
+    pub sftp_user: String,
+    pub npm_cert_path: String,
+    pub mailcow_dir: String,
+    pub private_key_path: String,
+}
+
+impl InitAnswers {
+    /// Builds a configuration with a single job from the answers. `mail_cert_path` is left empty,
+    /// it is derived from `mailcow_dir` when the configuration is loaded.
+    pub fn into_configuration(self) -> Configuration {
+        let mut configuration = Configuration::new();
+        configuration.buzz_urls = Vec::new();
+        configuration.jobs = vec![JobConfiguration {
+            sftp_host: self.sftp_host,
+            sftp_port: self.sftp_port,
+            sftp_user: self.sftp_user,
+            public_key_path: format!("{}.pub", self.private_key_path),
+            private_key_path: self.private_key_path,
+            npm_cert_path: self.npm_cert_path,
+            mail_cert_path: String::new(),
+            mailcow_dir: Some(self.mailcow_dir),
+            ..Configuration::new().jobs.remove(0)
+        }];
+        configuration
+    }
+}
+
+/// Asks questions on `output` and reads the answers from `input`. In defaults mode nothing is
+/// asked and the default is used, questions without a default fail.
+pub struct Prompt<R, W> {
+    input: R,
+    output: W,
+    defaults: bool,
+}
+
+impl<R: BufRead, W: Write> Prompt<R, W> {
+    pub fn new(input: R, output: W, defaults: bool) -> Prompt<R, W> {
+        Prompt {
+            input,
+            output,
+            defaults,
+        }
+    }
+
+    /// Asks for a value, an empty answer selects `default`.
+    pub fn ask(&mut self, question: &str, default: Option<&str>) -> Result<String, MoorenewError> {
+        if self.defaults {
+            return default.map(str::to_string).ok_or_else(|| {
+                MoorenewError::ConfigurationError(ConfigurationError::InitValueMissing(
+                    question.to_string(),
+                ))
+            });
+        }
+
+        loop {
+            match default {
+                Some(default) if !default.is_empty() => {
+                    write!(self.output, "{question} [{default}]: ")
+                }
+                _ => write!(self.output, "{question}: "),
+            }
+            .and_then(|_| self.output.flush())
+            .map_err(MoorenewError::Prompt)?;
+
+            let mut answer = String::new();
+            if self
+                .input
+                .read_line(&mut answer)
+                .map_err(MoorenewError::Prompt)?
+                == 0
+            {
+                return Err(MoorenewError::Prompt(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "input closed",
+                )));
+            }
+
+            match (answer.trim(), default) {
+                ("", Some(default)) => return Ok(default.to_string()),
+                ("", None) => continue,
+                (answer, _) => return Ok(answer.to_string()),
+            }
+        }
+    }
+
+    /// Asks a yes/no question, an empty answer selects `default`.
+    pub fn confirm(&mut self, question: &str, default: bool) -> Result<bool, MoorenewError> {
+        let hint = if default { "Y/n" } else { "y/N" };
+        loop {
+            let answer = self.ask(&format!("{question} ({hint})"), Some(""))?;
+            match answer.to_lowercase().as_str() {
+                "" => return Ok(default),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Prints a line, even in defaults mode.
+    pub fn say(&mut self, message: &str) -> Result<(), MoorenewError> {
+        writeln!(self.output, "{message}").map_err(MoorenewError::Prompt)
+    }
+
+    /// Asks every setup question which is not answered by `options`.
+    pub fn ask_answers(&mut self, options: &InitOptions) -> Result<InitAnswers, MoorenewError> {
+        let sftp_host = match &options.sftp_host {
+            Some(sftp_host) => sftp_host.clone(),
+            None => self.ask(
+                "Host serving the certificates (e.g. Nginx Proxy Manager)",
+                None,
+            )?,
+        };
+        let sftp_port = match options.sftp_port {
+            Some(sftp_port) => sftp_port,
+            None => loop {
+                match self.ask("SSH port", Some("22"))?.parse::<u16>() {
+                    Ok(port) => break port,
+                    Err(_) => self.say("please enter a port between 1 and 65535")?,
+                }
+            },
+        };
+        let sftp_user = match &options.sftp_user {
+            Some(sftp_user) => sftp_user.clone(),
+            None => self.ask("SSH user", Some(DEFAULT_SFTP_USER))?,
+        };
+        let npm_cert_path = match &options.npm_cert_path {
+            Some(npm_cert_path) => npm_cert_path.clone(),
+            None => self.ask(
+                "Remote directory containing fullchain.pem and privkey.pem",
+                Some(DEFAULT_NPM_CERT_PATH),
+            )?,
+        };
+        let mailcow_dir = match &options.mailcow_dir {
+            Some(mailcow_dir) => mailcow_dir.clone(),
+            None => self.ask("mailcow install directory", Some(DEFAULT_MAILCOW_DIR))?,
+        };
+        let private_key_path = match &options.private_key_path {
+            Some(private_key_path) => private_key_path.clone(),
+            None => {
+                let default_key = default_private_key_path()?;
+                self.ask("SSH private key", Some(&default_key.display().to_string()))?
+            }
+        };
+
+        Ok(InitAnswers {
+            sftp_host,
+            sftp_port,
+            sftp_user,
+            npm_cert_path,
+            mailcow_dir,
+            private_key_path,
+        })
+    }
+}
+
+/// run_init asks for the setup values, generates a key pair if needed, tests the connection and
+/// the remote certificates and writes the validated configuration to `config_path`.
+pub fn run_init<R: BufRead, W: Write>(
+    prompt: &mut Prompt<R, W>,
+    options: &InitOptions,
+    config_path: &Path,
+) -> Result<Configuration, MoorenewError> {
+    if config_path.exists() && !options.force {
+        return Err(MoorenewError::ConfigurationError(
+            ConfigurationError::ConfigFileExists(config_path.display().to_string()),
+        ));
+    }
+
+    let answers = prompt.ask_answers(options)?;
+    let configuration = answers.into_configuration();
+
+    // test and validate the configuration the way a run will see it
+    let mut resolved = configuration.clone();
+    for job in &mut resolved.jobs {
+        if let Err(e) = mailcow::discover(job) {
+            warn!(error = %e, "could not read the mailcow settings");
+        }
+    }
+    let job = &resolved.jobs[0];
+
+    if !Path::new(&job.private_key_path).exists()
+        && prompt.confirm(
+            &format!("Generate a new SSH key pair at {}?", job.private_key_path),
+            true,
+        )?
+    {
+        if let Some(key_dir) = Path::new(&job.private_key_path).parent() {
+            std::fs::create_dir_all(key_dir).map_err(|e| {
+                MoorenewError::ConfigurationError(ConfigurationError::DirectoryCreation(e))
+            })?;
+        }
+        let comment = format!(
+            "{}@{}",
+            sysinfo::get_loggedin_user()?,
+            sysinfo::get_hostname()?
+        );
+        sshkeygen::generate_rsa_keypair(
+            "ed25519",
+            &job.private_key_path,
+            &comment,
+            &job.sftp_host,
+            &job.sftp_port,
+        )?;
+        prompt.say(&format!(
+            "Add the content of {} to ~/.ssh/authorized_keys of {} on {}.",
+            job.public_key_path, job.sftp_user, job.sftp_host
+        ))?;
+    }
+
+    if prompt.confirm("Test the connection and the remote certificates now?", true)?
+        && let Err(e) = test_connection(job)
+    {
+        error!(error = %e, "connection test failed");
+        if options.defaults {
+            warn!("writing the configuration anyway, fix the problem before the first run");
+        } else if !prompt.confirm("Write the configuration anyway?", false)? {
+            return Err(e);
+        }
+    }
+
+    let problems = validate_configuration(&resolved);
+    for problem in &problems {
+        error!(field = %problem.field, "{}", problem.message);
+    }
+    if !problems.is_empty()
+        && (options.defaults || !prompt.confirm("Write the configuration anyway?", false)?)
+    {
+        return Err(MoorenewError::ConfigurationError(
+            ConfigurationError::ValidationFailed {
+                problems: problems.len(),
+            },
+        ));
+    }
+
+    configuration.write_to_file(config_path)?;
+    info!(config = %config_path.display(), "configuration written");
+
+    Ok(configuration)
+}
+
+/// Connects to the job's host and validates the remote certificate pair.
+fn test_connection(job: &JobConfiguration) -> Result<(), MoorenewError> {
+    let known_hosts_path = job.known_hosts_path()?;
+    let client = SSHClient::connect(
+        &resolve_route(job)?,
+        &SshAuthentication {
+            methods: &job.auth_methods,
+            private_key: job.private_key.as_deref(),
+            private_key_path: &job.private_key_path,
+            public_key_path: &job.public_key_path,
+            identity_files: &[],
+            passphrase: job.private_key_passphrase.as_deref(),
+        },
+        &HostKeyVerification {
+            policy: &job.host_key_policy,
+            fingerprint: job.host_key_fingerprint.as_deref(),
+            known_hosts_path: &known_hosts_path,
+        },
+        &job.connection,
+    )?;
+
+    let npm_cert_path = Path::new(&job.npm_cert_path);
+    let result = client
+        .read_file(&npm_cert_path.join("fullchain.pem"))
+        .and_then(|fullchain| {
+            let private_key = client.read_file(&npm_cert_path.join("privkey.pem"))?;
+            validate_certificate_pair(&fullchain, &private_key, &job.hostnames)
+        });
+    client.disconnect();
+
+    let certificate = result?;
+    info!(subject = %certificate.subject, not_after = %certificate.not_after, "remote certificates are valid");
+    Ok(())
+}
+
+fn default_private_key_path() -> Result<PathBuf, MoorenewError> {
+    Ok(std::env::home_dir()
+        .ok_or_else(|| MoorenewError::ConfigurationError(ConfigurationError::HomeDirUnavailable))?
+        .join(".ssh/moorenew"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InitAnswers, InitOptions, Prompt};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_ask_answers() {
+        let input = Cursor::new(b"npm.example.com\nabc\n2222\n\n\n/srv/mailcow\n/root/.ssh/id\n");
+        let mut output = Vec::new();
+        let answers = Prompt::new(input, &mut output, false)
+            .ask_answers(&InitOptions::default())
+            .unwrap();
+
+        assert_eq!(
+            answers,
+            InitAnswers {
+                sftp_host: "npm.example.com".to_string(),
+                sftp_port: 2222,
+                sftp_user: "mailcow".to_string(),
+                npm_cert_path: "/etc/letsencrypt/live/npm-1".to_string(),
+                mailcow_dir: "/srv/mailcow".to_string(),
+                private_key_path: "/root/.ssh/id".to_string(),
+            }
+        );
+        assert!(
+            String::from_utf8(output)
+                .unwrap()
+                .contains("SSH port [22]: ")
+        );
+
+        let configuration = answers.into_configuration();
+        assert_eq!(
+            configuration.jobs[0].mailcow_dir.as_deref(),
+            Some("/srv/mailcow")
+        );
+        assert!(configuration.jobs[0].mail_cert_path.is_empty());
+        assert_eq!(configuration.jobs[0].public_key_path, "/root/.ssh/id.pub");
+        assert!(configuration.buzz_urls.is_empty());
+    }
+
+    #[test]
+    fn test_defaults_require_host() {
+        let mut prompt = Prompt::new(Cursor::new(b""), Vec::new(), true);
+
+        assert!(prompt.ask_answers(&InitOptions::default()).is_err());
+
+        let answers = prompt
+            .ask_answers(&InitOptions {
+                sftp_host: Some("npm.example.com".to_string()),
+                ..InitOptions::default()
+            })
+            .unwrap();
+        assert_eq!(answers.sftp_port, 22);
+        assert_eq!(answers.mailcow_dir, "/opt/mailcow-dockerized");
+    }
+}
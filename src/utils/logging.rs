@@ -1,6 +1,6 @@
 use crate::system::sysinfo::get_hostname;
 use crate::utils::configuration::{Configuration, LokiConfiguration};
-use crate::utils::errors::{self, ConfigurationError, MoorenewError};
+use crate::utils::errors::{self, MoorenewError};
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
 use std::process;
@@ -39,10 +39,8 @@ pub fn setup_run_logging(level: &str, configuration: &Configuration) -> Result<(
     // Basis-Registry
     let subscriber = tracing_subscriber::registry().with(logging_level);
 
-    // Home
-    let user_path = std::env::home_dir()
-        .ok_or_else(|| MoorenewError::ConfigurationError(ConfigurationError::HomeDirUnavailable))?;
-    let moorenew_path = user_path.join(".moorenew");
+    // State directory
+    let moorenew_path = configuration.state_dir()?;
 
     // Check if loki logging is configured
     let loki_logging = configuration.logging.loki.is_some();
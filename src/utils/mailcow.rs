@@ -0,0 +1,201 @@
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+use crate::utils::configuration::JobConfiguration;
+use crate::utils::errors::{ConfigurationError, MoorenewError};
+
+/// Install directory of mailcow used if `mailcow_dir` is not configured.
+pub const DEFAULT_MAILCOW_DIR: &str = "/opt/mailcow-dockerized";
+
+/// Settings of a mailcow installation read from its `mailcow.conf`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MailcowConf {
+    pub compose_project_name: Option<String>,
+    pub hostname: Option<String>,
+    /// Set if mailcow's own ACME client is disabled.
+    pub skip_lets_encrypt: bool,
+    pub additional_san: Vec<String>,
+}
+
+impl MailcowConf {
+    /// Parses the `KEY=value` lines of `mailcow.conf`, ignoring comments and unknown keys.
+    pub fn parse(contents: &str) -> MailcowConf {
+        let mut conf = MailcowConf::default();
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .or_else(|| {
+                    value
+                        .strip_prefix('\'')
+                        .and_then(|value| value.strip_suffix('\''))
+                })
+                .unwrap_or(value)
+                .trim();
+
+            match key.trim() {
+                "COMPOSE_PROJECT_NAME" if !value.is_empty() => {
+                    conf.compose_project_name = Some(value.to_string())
+                }
+                "MAILCOW_HOSTNAME" if !value.is_empty() => conf.hostname = Some(value.to_string()),
+                "SKIP_LETS_ENCRYPT" => conf.skip_lets_encrypt = value.eq_ignore_ascii_case("y"),
+                "ADDITIONAL_SAN" => {
+                    conf.additional_san = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|san| !san.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                }
+                _ => {}
+            }
+        }
+
+        conf
+    }
+
+    pub fn read(mailcow_dir: &Path) -> Result<MailcowConf, MoorenewError> {
+        let path = mailcow_dir.join("mailcow.conf");
+        let contents = std::fs::read_to_string(&path).map_err(|e| {
+            MoorenewError::ConfigurationError(ConfigurationError::MailcowConf {
+                path: path.display().to_string(),
+                error: e,
+            })
+        })?;
+        Ok(MailcowConf::parse(&contents))
+    }
+
+    /// Hostnames the certificate has to cover. Entries of `ADDITIONAL_SAN` like `imap.*`, which
+    /// mailcow expands for every mail domain, are left out.
+    pub fn hostnames(&self) -> Vec<String> {
+        self.hostname
+            .iter()
+            .chain(self.additional_san.iter().filter(|san| !san.contains('*')))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Directory mailcow loads its certificates from.
+pub fn ssl_dir(mailcow_dir: &Path) -> PathBuf {
+    mailcow_dir.join("data/assets/ssl")
+}
+
+/// discover fills the fields of `job` which are not configured from its mailcow installation:
+/// `mail_cert_path` becomes the SSL directory, `hostnames` the mailcow hostname and additional
+/// names and `compose_project` the compose project used to find the containers.
+///
+/// The installation is `mailcow_dir` if set. Otherwise `/opt/mailcow-dockerized` is used if
+/// `mail_cert_path` is not set or points into it, and a missing `mailcow.conf` is not an error.
+pub fn discover(job: &mut JobConfiguration) -> Result<Option<MailcowConf>, MoorenewError> {
+    let mailcow_dir = match &job.mailcow_dir {
+        Some(mailcow_dir) => PathBuf::from(mailcow_dir),
+        None => {
+            let default_dir = PathBuf::from(DEFAULT_MAILCOW_DIR);
+            if !job.mail_cert_path.is_empty()
+                && Path::new(&job.mail_cert_path) != ssl_dir(&default_dir)
+            {
+                return Ok(None);
+            }
+            default_dir
+        }
+    };
+
+    if job.mail_cert_path.is_empty() {
+        job.mail_cert_path = ssl_dir(&mailcow_dir).display().to_string();
+    }
+
+    if job.mailcow_dir.is_none() && !mailcow_dir.join("mailcow.conf").exists() {
+        debug!(mailcow_dir = %mailcow_dir.display(), "no mailcow.conf found");
+        return Ok(None);
+    }
+
+    let conf = MailcowConf::read(&mailcow_dir)?;
+    info!(
+        job = %job.name,
+        hostname = ?conf.hostname,
+        compose_project = ?conf.compose_project_name,
+        "read mailcow.conf"
+    );
+
+    if !conf.skip_lets_encrypt {
+        warn!(
+            job = %job.name,
+            "mailcow's ACME client is enabled and will overwrite the installed certificates, set SKIP_LETS_ENCRYPT=y in mailcow.conf"
+        );
+    }
+
+    if job.hostnames.is_empty() {
+        job.hostnames = conf.hostnames();
+    }
+    if job.compose_project.is_none() {
+        job.compose_project = conf.compose_project_name.clone();
+    }
+
+    Ok(Some(conf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MailcowConf, discover};
+    use crate::utils::configuration::Configuration;
+    use crate::utils::testutil::TempDir;
+    use std::fs;
+
+    const MAILCOW_CONF: &str = r#"
+# ------------------------------
+# mailcow web ui configuration
+# ------------------------------
+MAILCOW_HOSTNAME=mail.example.com
+COMPOSE_PROJECT_NAME="mailcowdockerized"
+ADDITIONAL_SAN=imap.*,smtp.*,autodiscover.example.org
+# SKIP_LETS_ENCRYPT=n
+SKIP_LETS_ENCRYPT=y
+"#;
+
+    #[test]
+    fn test_parse_mailcow_conf() {
+        let conf = MailcowConf::parse(MAILCOW_CONF);
+
+        assert_eq!(conf.hostname.as_deref(), Some("mail.example.com"));
+        assert_eq!(
+            conf.compose_project_name.as_deref(),
+            Some("mailcowdockerized")
+        );
+        assert!(conf.skip_lets_encrypt);
+        assert_eq!(
+            conf.hostnames(),
+            vec!["mail.example.com", "autodiscover.example.org"]
+        );
+    }
+
+    #[test]
+    fn test_discover() {
+        let root = TempDir::new("mailcow");
+        fs::write(root.join("mailcow.conf"), MAILCOW_CONF).unwrap();
+
+        let mut job = Configuration::new().jobs.remove(0);
+        job.mail_cert_path = String::new();
+        job.mailcow_dir = Some(root.display().to_string());
+        discover(&mut job).unwrap();
+
+        assert_eq!(
+            job.mail_cert_path,
+            root.join("data/assets/ssl").display().to_string()
+        );
+        assert_eq!(job.compose_project.as_deref(), Some("mailcowdockerized"));
+        assert_eq!(job.hostnames.len(), 2);
+
+        job.mailcow_dir = Some(root.join("missing").display().to_string());
+        assert!(discover(&mut job).is_err());
+    }
+}
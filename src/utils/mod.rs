@@ -1,7 +1,23 @@
+pub mod acme;
 pub mod certificates;
+pub mod certvalidation;
 pub mod configuration;
+pub mod docker;
 pub mod errors;
+pub mod expiry;
 pub mod fileext;
+pub mod history;
+pub mod http;
+pub mod init;
 pub mod logging;
+pub mod mailcow;
+pub mod npm;
+pub mod source;
 pub mod ssh;
+pub mod sshconfig;
 pub mod sshkeygen;
+pub mod sshtunnel;
+pub mod state;
+#[cfg(test)]
+pub mod testutil;
+pub mod tlsverify;
@@ -0,0 +1,143 @@
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+use crate::utils::certvalidation::issued_at;
+use crate::utils::errors::MoorenewError;
+use crate::utils::source::FileAccess;
+
+/// Directory Nginx Proxy Manager keeps its certificates in, used if `npm_live_path` is not set.
+pub const DEFAULT_NPM_LIVE_PATH: &str = "/etc/letsencrypt/live";
+
+/// Prefix of the certificate directories created by Nginx Proxy Manager, e.g. `npm-17`.
+const NPM_DIR_PREFIX: &str = "npm-";
+
+/// find_certificate_dir returns the `npm-*` directory in `live_path` containing the most recently
+/// issued `fullchain.pem` which is currently valid and covers `domain`. Nginx Proxy Manager creates
+/// a new directory whenever a certificate is re-issued, so the directory of a domain changes over
+/// time.
+pub fn find_certificate_dir<F: FileAccess>(
+    files: &F,
+    live_path: &Path,
+    domain: &str,
+) -> Result<PathBuf, MoorenewError> {
+    let mut newest: Option<(i64, PathBuf)> = None;
+
+    for directory in files.list_dir(live_path)? {
+        let is_npm_dir = directory
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with(NPM_DIR_PREFIX));
+        if !is_npm_dir {
+            continue;
+        }
+
+        let fullchain = match files.read_file(&directory.join("fullchain.pem")) {
+            Ok(fullchain) => fullchain,
+            Err(e) => {
+                debug!(error = %e, directory = %directory.display(), "skipping directory without readable fullchain.pem");
+                continue;
+            }
+        };
+
+        match issued_at(&fullchain, domain) {
+            Ok(Some(issued_at)) => {
+                debug!(directory = %directory.display(), issued_at, "found matching certificate");
+                if newest
+                    .as_ref()
+                    .is_none_or(|(newest_issued_at, _)| issued_at > *newest_issued_at)
+                {
+                    newest = Some((issued_at, directory));
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!(error = %e, directory = %directory.display(), "could not parse fullchain.pem")
+            }
+        }
+    }
+
+    let (_, directory) = newest.ok_or_else(|| MoorenewError::CertificateNotFound {
+        domain: domain.to_string(),
+        path: live_path.display().to_string(),
+    })?;
+    info!(domain, directory = %directory.display(), "found certificate directory");
+
+    Ok(directory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_certificate_dir;
+    use crate::utils::errors::MoorenewError;
+    use crate::utils::source::FileAccess;
+    use crate::utils::testutil::self_signed_pair;
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    struct MockFiles {
+        files: HashMap<PathBuf, Vec<u8>>,
+    }
+
+    impl FileAccess for MockFiles {
+        fn list_dir(&self, remote_path: &Path) -> Result<Vec<PathBuf>, MoorenewError> {
+            let mut entries: Vec<PathBuf> = self
+                .files
+                .keys()
+                .filter_map(|path| path.parent())
+                .filter(|parent| parent.parent() == Some(remote_path))
+                .map(Path::to_path_buf)
+                .collect();
+            entries.sort();
+            entries.dedup();
+            Ok(entries)
+        }
+
+        fn read_file(&self, remote_path: &Path) -> Result<Vec<u8>, MoorenewError> {
+            self.files
+                .get(remote_path)
+                .cloned()
+                .ok_or(MoorenewError::NoChanges)
+        }
+    }
+
+    fn fullchain(dns_name: &str, age_days: i64, valid_days: i64) -> Vec<u8> {
+        self_signed_pair(dns_name, age_days, valid_days).0
+    }
+
+    #[test]
+    fn test_find_certificate_dir() {
+        let live = Path::new("/etc/letsencrypt/live");
+        let files = MockFiles {
+            files: HashMap::from([
+                (
+                    live.join("npm-1/fullchain.pem"),
+                    fullchain("mail.example.com", 60, 30),
+                ),
+                (
+                    live.join("npm-7/fullchain.pem"),
+                    fullchain("mail.example.com", 5, 85),
+                ),
+                (
+                    live.join("npm-9/fullchain.pem"),
+                    fullchain("mail.example.com", 1, -1),
+                ),
+                (
+                    live.join("npm-12/fullchain.pem"),
+                    fullchain("mail.example.org", 1, 89),
+                ),
+                (
+                    live.join("README/fullchain.pem"),
+                    fullchain("mail.example.com", 0, 90),
+                ),
+            ]),
+        };
+
+        assert_eq!(
+            find_certificate_dir(&files, live, "mail.example.com").unwrap(),
+            live.join("npm-7")
+        );
+        assert!(matches!(
+            find_certificate_dir(&files, live, "mail.example.net"),
+            Err(MoorenewError::CertificateNotFound { .. })
+        ));
+    }
+}
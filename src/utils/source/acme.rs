@@ -0,0 +1,198 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, info};
+
+use super::{CertificatePair, CertificateSource};
+use crate::utils::acme::{
+    self, AccountKey, AcmeClient, ChallengeSolver, Dns01Solver, Http01Solver, TsigKey,
+};
+use crate::utils::certvalidation::{covers_hostnames, days_until_expiry};
+use crate::utils::configuration::{AcmeChallengeConfiguration, AcmeConfiguration};
+use crate::utils::errors::MoorenewError;
+
+/// A certificate ordered from an ACME server once the installed one is due for renewal.
+pub struct AcmeSource<'a> {
+    configuration: &'a AcmeConfiguration,
+    hostnames: &'a [String],
+    /// Directory of the installed `cert.pem` and `key.pem`.
+    installed_path: PathBuf,
+    state_dir: PathBuf,
+    connect_timeout: Duration,
+    dry_run: bool,
+}
+
+impl<'a> AcmeSource<'a> {
+    /// Creates a source for `hostnames`, renewing the certificate installed in `installed_path`.
+    /// The account key is kept in `state_dir`. Dry runs order from the staging directory.
+    pub fn new(
+        configuration: &'a AcmeConfiguration,
+        hostnames: &'a [String],
+        installed_path: &Path,
+        state_dir: &Path,
+        connect_timeout: Duration,
+        dry_run: bool,
+    ) -> AcmeSource<'a> {
+        AcmeSource {
+            configuration,
+            hostnames,
+            installed_path: installed_path.to_path_buf(),
+            state_dir: state_dir.to_path_buf(),
+            connect_timeout,
+            dry_run,
+        }
+    }
+
+    fn directory_url(&self) -> &str {
+        match (&self.configuration.dry_run_directory_url, self.dry_run) {
+            (Some(url), true) => url,
+            (None, true)
+                if self.configuration.directory_url == acme::LETS_ENCRYPT_DIRECTORY_URL =>
+            {
+                acme::LETS_ENCRYPT_STAGING_DIRECTORY_URL
+            }
+            _ => &self.configuration.directory_url,
+        }
+    }
+
+    /// Returns the installed pair if it covers the hostnames and does not expire within
+    /// `renew_before` days.
+    fn current_pair(&self) -> Option<CertificatePair> {
+        let pair = CertificatePair {
+            fullchain: std::fs::read(self.installed_path.join("cert.pem")).ok()?,
+            private_key: std::fs::read(self.installed_path.join("key.pem")).ok()?,
+        };
+
+        let days = days_until_expiry(&pair.fullchain).ok()?;
+        if days < self.configuration.renew_before as i32 {
+            info!(days, "installed certificate is due for renewal");
+            return None;
+        }
+        if !covers_hostnames(&pair.fullchain, self.hostnames).unwrap_or(false) {
+            info!("installed certificate does not cover all hostnames");
+            return None;
+        }
+
+        debug!(days, "installed certificate is not due for renewal");
+        Some(pair)
+    }
+
+    fn order(&self) -> Result<CertificatePair, MoorenewError> {
+        let directory_url = self.directory_url();
+        let key =
+            AccountKey::load_or_generate(&acme::account_key_path(&self.state_dir, directory_url))
+                .map_err(MoorenewError::Acme)?;
+
+        let mut solver: Box<dyn ChallengeSolver> = match &self.configuration.challenge {
+            AcmeChallengeConfiguration::Http01 { listen, webroot } => Box::new(Http01Solver::new(
+                listen.clone(),
+                webroot.as_ref().map(PathBuf::from),
+            )),
+            AcmeChallengeConfiguration::Dns01 {
+                nameserver,
+                zone,
+                tsig_key_name,
+                tsig_algorithm,
+                tsig_secret,
+                ttl,
+                propagation_delay,
+            } => Box::new(Dns01Solver::new(
+                nameserver.clone(),
+                zone.clone(),
+                TsigKey::new(tsig_key_name, tsig_algorithm, tsig_secret)?,
+                *ttl,
+                Duration::from_secs(*propagation_delay),
+            )),
+        };
+
+        info!(directory_url, "ordering certificate");
+        let mut client = AcmeClient::connect(
+            directory_url,
+            self.configuration.ca_file.clone(),
+            self.connect_timeout,
+            key,
+        )
+        .map_err(MoorenewError::Acme)?;
+        client
+            .register(&self.configuration.contact)
+            .map_err(MoorenewError::Acme)?;
+        client
+            .order_certificate(self.hostnames, self.configuration.key_type, solver.as_mut())
+            .map_err(MoorenewError::Acme)
+    }
+}
+
+impl CertificateSource for AcmeSource<'_> {
+    fn location(&self) -> String {
+        self.directory_url().to_string()
+    }
+
+    fn fetch(&self) -> Result<CertificatePair, MoorenewError> {
+        self.order()
+    }
+
+    /// Returns the checksums of the installed pair while it is not due for renewal, so the job
+    /// reports no changes. Otherwise a new certificate is ordered by the fetch.
+    fn checksums(&self) -> Result<Option<(String, String)>, MoorenewError> {
+        Ok(self.current_pair().map(|pair| pair.checksums()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AcmeSource;
+    use crate::utils::acme::LETS_ENCRYPT_STAGING_DIRECTORY_URL;
+    use crate::utils::configuration::{AcmeChallengeConfiguration, AcmeConfiguration, AcmeKeyType};
+    use crate::utils::errors::MoorenewError;
+    use crate::utils::source::{CertificatePair, CertificateSource};
+    use crate::utils::testutil::{TempDir, self_signed_pair};
+    use std::time::Duration;
+
+    #[test]
+    fn test_acme_renewal() {
+        let root = TempDir::new("acme");
+        let hostnames = vec!["mail.example.com".to_string()];
+        let configuration = AcmeConfiguration {
+            // nothing listens here, so every order fails
+            directory_url: "https://127.0.0.1:1/dir".to_string(),
+            dry_run_directory_url: None,
+            contact: Vec::new(),
+            ca_file: None,
+            key_type: AcmeKeyType::EcdsaP256,
+            renew_before: 30,
+            challenge: AcmeChallengeConfiguration::Http01 {
+                listen: "127.0.0.1:0".to_string(),
+                webroot: None,
+            },
+        };
+        let timeout = Duration::from_secs(10);
+        let source = AcmeSource::new(&configuration, &hostnames, &root, &root, timeout, false);
+
+        let install = |dns_name: &str, valid_days: i64| {
+            let (certificate, key) = self_signed_pair(dns_name, 1, valid_days);
+            std::fs::write(root.join("cert.pem"), &certificate).unwrap();
+            std::fs::write(root.join("key.pem"), &key).unwrap();
+            CertificatePair {
+                fullchain: certificate,
+                private_key: key,
+            }
+        };
+
+        let installed = install("mail.example.com", 60);
+        assert_eq!(source.checksums().unwrap(), Some(installed.checksums()));
+
+        install("mail.example.com", 10);
+        assert_eq!(source.checksums().unwrap(), None);
+        assert!(matches!(source.fetch(), Err(MoorenewError::Acme(_))));
+        install("mail.example.org", 60);
+        assert_eq!(source.checksums().unwrap(), None);
+
+        let staging = AcmeConfiguration {
+            directory_url: crate::utils::acme::LETS_ENCRYPT_DIRECTORY_URL.to_string(),
+            ..configuration
+        };
+        assert_eq!(
+            AcmeSource::new(&staging, &hostnames, &root, &root, timeout, true).location(),
+            LETS_ENCRYPT_STAGING_DIRECTORY_URL
+        );
+    }
+}
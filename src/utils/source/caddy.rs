@@ -0,0 +1,137 @@
+use std::path::PathBuf;
+use tracing::debug;
+
+use super::{CertificatePair, CertificateSource, FileAccess, newest_pair};
+use crate::utils::errors::MoorenewError;
+
+/// A certificate in Caddy's storage directory, stored as
+/// `certificates/<issuer>/<domain>/<domain>.crt` and `.key`.
+pub struct CaddySource<'a> {
+    files: &'a dyn FileAccess,
+    path: PathBuf,
+    domain: String,
+    issuer: Option<String>,
+}
+
+impl<'a> CaddySource<'a> {
+    /// Reads the storage directory at `path` through `files`. Without an `issuer` the certificates
+    /// of every issuer are considered.
+    pub fn new(
+        files: &'a dyn FileAccess,
+        path: PathBuf,
+        domain: String,
+        issuer: Option<String>,
+    ) -> CaddySource<'a> {
+        CaddySource {
+            files,
+            path,
+            domain,
+            issuer,
+        }
+    }
+}
+
+impl CertificateSource for CaddySource<'_> {
+    fn location(&self) -> String {
+        format!("{} ({})", self.path.display(), self.domain)
+    }
+
+    fn fetch(&self) -> Result<CertificatePair, MoorenewError> {
+        let certificates = self.path.join("certificates");
+        let issuers = match &self.issuer {
+            Some(issuer) => vec![certificates.join(issuer)],
+            None => self.files.list_dir(&certificates)?,
+        };
+        // Caddy replaces the wildcard label of a name in its storage keys
+        let name = self.domain.replace('*', "wildcard_");
+
+        let candidates = issuers.into_iter().filter_map(|issuer| {
+            let directory = issuer.join(&name);
+            let pair = self
+                .files
+                .read_file(&directory.join(format!("{name}.crt")))
+                .and_then(|fullchain| {
+                    Ok(CertificatePair {
+                        fullchain,
+                        private_key: self
+                            .files
+                            .read_file(&directory.join(format!("{name}.key")))?,
+                    })
+                });
+            match pair {
+                Ok(pair) => Some((directory, pair)),
+                Err(e) => {
+                    debug!(error = %e, directory = %directory.display(), "no certificate found");
+                    None
+                }
+            }
+        });
+
+        newest_pair(candidates, &self.domain)
+            .map(|(_, pair)| pair)
+            .ok_or_else(|| MoorenewError::CertificateNotFound {
+                domain: self.domain.clone(),
+                path: certificates.display().to_string(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CaddySource;
+    use crate::utils::source::{CertificateSource, LocalFiles};
+    use crate::utils::testutil::{TempDir, self_signed_pair};
+    use std::fs;
+
+    #[test]
+    fn test_caddy_source() {
+        let root = TempDir::new("caddy");
+        let write = |issuer: &str, name: &str, (certificate, key): &(Vec<u8>, Vec<u8>)| {
+            let directory = root.join("certificates").join(issuer).join(name);
+            fs::create_dir_all(&directory).unwrap();
+            fs::write(directory.join(format!("{name}.crt")), certificate).unwrap();
+            fs::write(directory.join(format!("{name}.key")), key).unwrap();
+        };
+        let letsencrypt = self_signed_pair("mail.example.com", 30, 60);
+        let zerossl = self_signed_pair("mail.example.com", 2, 88);
+        write(
+            "acme-v02.api.letsencrypt.org-directory",
+            "mail.example.com",
+            &letsencrypt,
+        );
+        write("acme.zerossl.com-v2-dv90", "mail.example.com", &zerossl);
+        write(
+            "acme-v02.api.letsencrypt.org-directory",
+            "wildcard_.example.org",
+            &self_signed_pair("*.example.org", 0, 90),
+        );
+
+        let source = |domain: &str, issuer: Option<&str>| {
+            CaddySource::new(
+                &LocalFiles,
+                root.to_path_buf(),
+                domain.to_string(),
+                issuer.map(str::to_string),
+            )
+        };
+        assert_eq!(
+            source("mail.example.com", None)
+                .fetch()
+                .unwrap()
+                .private_key,
+            zerossl.1
+        );
+        assert_eq!(
+            source(
+                "mail.example.com",
+                Some("acme-v02.api.letsencrypt.org-directory")
+            )
+            .fetch()
+            .unwrap()
+            .private_key,
+            letsencrypt.1
+        );
+        assert!(source("*.example.org", None).fetch().is_ok());
+        assert!(source("mail.example.net", None).fetch().is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use std::time::Duration;
+use tracing::info;
+use url::Url;
+
+use super::{CertificatePair, CertificateSource};
+use crate::utils::errors::{ConfigurationError, MoorenewError};
+use crate::utils::http;
+
+/// `fullchain.pem` and `privkey.pem` below an HTTPS URL, requested with an optional bearer token.
+/// The server certificate is verified against the system trust store or `ca_file`.
+pub struct HttpsSource {
+    url: Url,
+    token: Option<String>,
+    ca_file: Option<String>,
+    connect_timeout: Duration,
+}
+
+impl HttpsSource {
+    pub fn new(
+        url: &str,
+        token: Option<String>,
+        ca_file: Option<String>,
+        connect_timeout: Duration,
+    ) -> Result<HttpsSource, MoorenewError> {
+        let mut url = Url::parse(url).map_err(|e| {
+            MoorenewError::ConfigurationError(ConfigurationError::InvalidSourceUrl(e.to_string()))
+        })?;
+        // the private key must not be sent in plain text
+        if url.scheme() != "https" {
+            return Err(MoorenewError::ConfigurationError(
+                ConfigurationError::InvalidSourceUrl(format!(
+                    "unsupported scheme `{}`, use https",
+                    url.scheme()
+                )),
+            ));
+        }
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+
+        Ok(HttpsSource {
+            url,
+            token,
+            ca_file,
+            connect_timeout,
+        })
+    }
+
+    fn get(&self, file: &str) -> Result<Vec<u8>, MoorenewError> {
+        let url = self.url.join(file).map_err(|e| {
+            MoorenewError::ConfigurationError(ConfigurationError::InvalidSourceUrl(e.to_string()))
+        })?;
+        let location = url.to_string();
+        info!("downloading {location}");
+
+        self.request(&url)
+            .map_err(|error| MoorenewError::CertificateSource { location, error })
+    }
+
+    fn request(&self, url: &Url) -> std::io::Result<Vec<u8>> {
+        let authorization = self.token.as_ref().map(|token| format!("Bearer {token}"));
+        let mut headers = vec![("Accept", "application/x-pem-file")];
+        if let Some(authorization) = &authorization {
+            headers.push(("Authorization", authorization));
+        }
+
+        let response = http::https_request(
+            url,
+            "GET",
+            &headers,
+            None,
+            self.ca_file.as_deref(),
+            self.connect_timeout,
+        )?;
+        if !response.is_success() {
+            return Err(std::io::Error::other(format!(
+                "server responded with status {}",
+                response.status
+            )));
+        }
+
+        Ok(response.body)
+    }
+}
+
+impl CertificateSource for HttpsSource {
+    fn location(&self) -> String {
+        self.url.to_string()
+    }
+
+    fn fetch(&self) -> Result<CertificatePair, MoorenewError> {
+        Ok(CertificatePair {
+            fullchain: self.get("fullchain.pem")?,
+            private_key: self.get("privkey.pem")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HttpsSource;
+    use crate::utils::source::CertificateSource;
+    use crate::utils::testutil::{TempDir, self_signed_pair};
+    use openssl::pkey::PKey;
+    use openssl::ssl::{SslAcceptor, SslMethod};
+    use openssl::x509::X509;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    /// Serves `requests` requests, answering requests with the bearer token `secret` with the
+    /// requested path and all others with 401. The certificate of the server is written to
+    /// `ca.pem` in the returned directory.
+    fn https_server(requests: usize) -> (u16, TempDir) {
+        let (certificate, key) = self_signed_pair("localhost", 0, 1);
+        let root = TempDir::new("https");
+        std::fs::write(root.join("ca.pem"), &certificate).unwrap();
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor
+            .set_certificate(&X509::from_pem(&certificate).unwrap())
+            .unwrap();
+        acceptor
+            .set_private_key(&PKey::private_key_from_pem(&key).unwrap())
+            .unwrap();
+        let acceptor = acceptor.build();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = acceptor.accept(stream.unwrap()).unwrap();
+                let mut reader = BufReader::new(&mut stream);
+                let mut path = String::new();
+                let mut authorized = false;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.starts_with("GET ") {
+                        path = line.split_whitespace().nth(1).unwrap().to_string();
+                    }
+                    authorized |= line.trim_end() == "Authorization: Bearer secret";
+                    if line.trim_end().is_empty() {
+                        break;
+                    }
+                }
+                let response = if authorized {
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{path}",
+                        path.len()
+                    )
+                } else {
+                    "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n".to_string()
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        (port, root)
+    }
+
+    #[test]
+    fn test_https_source() {
+        let (port, root) = https_server(3);
+        let ca_file = root.join("ca.pem").display().to_string();
+        let url = format!("https://localhost:{port}/certificates/mail");
+
+        let timeout = Duration::from_secs(10);
+        let source = HttpsSource::new(
+            &url,
+            Some("secret".to_string()),
+            Some(ca_file.clone()),
+            timeout,
+        )
+        .unwrap();
+        let pair = source.fetch().unwrap();
+        assert_eq!(pair.fullchain, b"/certificates/mail/fullchain.pem");
+        assert_eq!(pair.private_key, b"/certificates/mail/privkey.pem");
+
+        let source = HttpsSource::new(&url, None, Some(ca_file.clone()), timeout).unwrap();
+        assert!(source.fetch().is_err());
+
+        assert!(HttpsSource::new("http://localhost/certificates", None, None, timeout).is_err());
+    }
+}
@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+
+use super::{CertificatePair, CertificateSource, FileAccess, LocalFiles};
+use crate::utils::errors::MoorenewError;
+
+/// `fullchain.pem` and `privkey.pem` in a local directory, e.g. `/etc/letsencrypt/live/<domain>`
+/// of certbot or Nginx Proxy Manager running on the same host as mailcow.
+pub struct LocalSource {
+    path: PathBuf,
+}
+
+impl LocalSource {
+    pub fn new(path: PathBuf) -> LocalSource {
+        LocalSource { path }
+    }
+}
+
+impl CertificateSource for LocalSource {
+    fn location(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn fetch(&self) -> Result<CertificatePair, MoorenewError> {
+        Ok(CertificatePair {
+            fullchain: LocalFiles.read_file(&self.path.join("fullchain.pem"))?,
+            private_key: LocalFiles.read_file(&self.path.join("privkey.pem"))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LocalSource;
+    use crate::utils::source::CertificateSource;
+    use crate::utils::testutil::TempDir;
+    use std::fs;
+
+    #[test]
+    fn test_local_source() {
+        let root = TempDir::new("local");
+        fs::write(root.join("fullchain.pem"), "chain").unwrap();
+        fs::write(root.join("privkey.pem"), "key").unwrap();
+
+        let source = LocalSource::new(root.to_path_buf());
+        assert_eq!(source.fetch().unwrap().fullchain, b"chain");
+        // same format as the checksums of the installed files and of sha256sum
+        assert_eq!(
+            source.fetch().unwrap().checksums().1,
+            "2c70e12b7a0646f92279f427c7b38e7334d8e5389cff167a1dc30e73f826b683"
+        );
+
+        fs::remove_file(root.join("privkey.pem")).unwrap();
+        assert!(source.fetch().is_err());
+    }
+}
@@ -0,0 +1,119 @@
+mod acme;
+mod caddy;
+mod https;
+mod local;
+mod sftp;
+mod traefik;
+
+pub use self::acme::AcmeSource;
+pub use self::caddy::CaddySource;
+pub use self::https::HttpsSource;
+pub use self::local::LocalSource;
+pub use self::sftp::SftpSource;
+pub use self::traefik::TraefikSource;
+
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+use crate::utils::certvalidation::issued_at;
+use crate::utils::errors::MoorenewError;
+use crate::utils::ssh::SSHClient;
+
+/// A certificate chain and its private key, both PEM encoded.
+#[derive(Clone)]
+pub struct CertificatePair {
+    pub fullchain: Vec<u8>,
+    pub private_key: Vec<u8>,
+}
+
+impl CertificatePair {
+    /// SHA-256 checksums of the chain and the key, in the format of [`CertificateSource::checksums`].
+    pub fn checksums(&self) -> (String, String) {
+        (sha256_hex(&self.fullchain), sha256_hex(&self.private_key))
+    }
+}
+
+/// A place certificates are downloaded from.
+pub trait CertificateSource {
+    /// Location of the certificates for logs and errors.
+    fn location(&self) -> String;
+
+    /// Downloads the certificate chain and the private key.
+    fn fetch(&self) -> Result<CertificatePair, MoorenewError>;
+
+    /// Lowercase hex encoded SHA-256 checksums of the chain and the private key, compared with the
+    /// installed `cert.pem` and `key.pem` to skip unchanged certificates. `None` if they are only
+    /// known after fetching the certificates, sources which can calculate them without downloading
+    /// the files override this.
+    fn checksums(&self) -> Result<Option<(String, String)>, MoorenewError> {
+        Ok(None)
+    }
+}
+
+/// Read access to the files of a source, either local or over SFTP.
+pub trait FileAccess {
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>, MoorenewError>;
+    fn read_file(&self, path: &Path) -> Result<Vec<u8>, MoorenewError>;
+}
+
+impl FileAccess for SSHClient {
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>, MoorenewError> {
+        SSHClient::list_dir(self, path)
+    }
+
+    fn read_file(&self, path: &Path) -> Result<Vec<u8>, MoorenewError> {
+        SSHClient::read_file(self, path)
+    }
+}
+
+/// Files of the local file system.
+pub struct LocalFiles;
+
+impl FileAccess for LocalFiles {
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>, MoorenewError> {
+        std::fs::read_dir(path)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect()
+            })
+            .map_err(|e| source_error(path, e))
+    }
+
+    fn read_file(&self, path: &Path) -> Result<Vec<u8>, MoorenewError> {
+        std::fs::read(path).map_err(|e| source_error(path, e))
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Returns the candidate with the most recently issued certificate which is currently valid and
+/// covers `domain`. Sources keeping several certificates for a domain use this to skip expired and
+/// superseded ones.
+fn newest_pair<T>(
+    candidates: impl IntoIterator<Item = (T, CertificatePair)>,
+    domain: &str,
+) -> Option<(T, CertificatePair)> {
+    candidates
+        .into_iter()
+        .filter_map(
+            |(candidate, pair)| match issued_at(&pair.fullchain, domain) {
+                Ok(Some(issued_at)) => Some((issued_at, candidate, pair)),
+                _ => None,
+            },
+        )
+        .max_by_key(|(issued_at, _, _)| *issued_at)
+        .map(|(_, candidate, pair)| (candidate, pair))
+}
+
+fn source_error(location: impl AsRef<Path>, error: std::io::Error) -> MoorenewError {
+    MoorenewError::CertificateSource {
+        location: location.as_ref().display().to_string(),
+        error,
+    }
+}
@@ -0,0 +1,41 @@
+use std::path::PathBuf;
+
+use super::{CertificatePair, CertificateSource};
+use crate::utils::errors::MoorenewError;
+use crate::utils::ssh::SSHClient;
+
+/// `fullchain.pem` and `privkey.pem` in a directory on the SFTP host, e.g. a certificate
+/// directory of Nginx Proxy Manager.
+pub struct SftpSource<'a> {
+    client: &'a SSHClient,
+    path: PathBuf,
+}
+
+impl<'a> SftpSource<'a> {
+    pub fn new(client: &'a SSHClient, path: PathBuf) -> SftpSource<'a> {
+        SftpSource { client, path }
+    }
+}
+
+impl CertificateSource for SftpSource<'_> {
+    fn location(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn fetch(&self) -> Result<CertificatePair, MoorenewError> {
+        Ok(CertificatePair {
+            fullchain: self.client.read_file(&self.path.join("fullchain.pem"))?,
+            private_key: self.client.read_file(&self.path.join("privkey.pem"))?,
+        })
+    }
+
+    /// Calculates the checksums on the remote host, so unchanged certificates are not downloaded.
+    fn checksums(&self) -> Result<Option<(String, String)>, MoorenewError> {
+        Ok(Some((
+            self.client
+                .get_remote_sha256(&self.path.join("fullchain.pem"))?,
+            self.client
+                .get_remote_sha256(&self.path.join("privkey.pem"))?,
+        )))
+    }
+}
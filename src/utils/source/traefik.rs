@@ -0,0 +1,153 @@
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use super::{CertificatePair, CertificateSource, FileAccess, newest_pair, source_error};
+use crate::utils::errors::MoorenewError;
+
+/// A certificate stored by Traefik's ACME resolvers in `acme.json`.
+pub struct TraefikSource<'a> {
+    files: &'a dyn FileAccess,
+    path: PathBuf,
+    domain: String,
+    resolver: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Resolver {
+    #[serde(rename = "Certificates", default)]
+    certificates: Option<Vec<AcmeCertificate>>,
+}
+
+#[derive(Deserialize)]
+struct AcmeCertificate {
+    /// Base64 encoded PEM chain.
+    certificate: String,
+    /// Base64 encoded PEM private key.
+    key: String,
+}
+
+impl<'a> TraefikSource<'a> {
+    /// Reads `acme.json` at `path` through `files`. Only the certificates of `resolver` are
+    /// considered if it is set.
+    pub fn new(
+        files: &'a dyn FileAccess,
+        path: PathBuf,
+        domain: String,
+        resolver: Option<String>,
+    ) -> TraefikSource<'a> {
+        TraefikSource {
+            files,
+            path,
+            domain,
+            resolver,
+        }
+    }
+
+    fn decode(&self, value: &str) -> Result<Vec<u8>, MoorenewError> {
+        BASE64_STANDARD
+            .decode(value)
+            .map_err(|e| source_error(&self.path, std::io::Error::other(e)))
+    }
+}
+
+impl CertificateSource for TraefikSource<'_> {
+    fn location(&self) -> String {
+        format!("{} ({})", self.path.display(), self.domain)
+    }
+
+    /// Returns the most recently issued valid certificate covering the domain, Traefik keeps
+    /// certificates of removed routers until they expire.
+    fn fetch(&self) -> Result<CertificatePair, MoorenewError> {
+        let contents = self.files.read_file(&self.path)?;
+        let resolvers: BTreeMap<String, Resolver> = serde_json::from_slice(&contents)
+            .map_err(|e| source_error(&self.path, std::io::Error::other(e)))?;
+
+        let mut candidates = Vec::new();
+        for (name, resolver) in resolvers {
+            if self
+                .resolver
+                .as_ref()
+                .is_some_and(|resolver| *resolver != name)
+            {
+                continue;
+            }
+            for certificate in resolver.certificates.unwrap_or_default() {
+                candidates.push((
+                    (),
+                    CertificatePair {
+                        fullchain: self.decode(&certificate.certificate)?,
+                        private_key: self.decode(&certificate.key)?,
+                    },
+                ));
+            }
+        }
+
+        newest_pair(candidates, &self.domain)
+            .map(|(_, pair)| pair)
+            .ok_or_else(|| MoorenewError::CertificateNotFound {
+                domain: self.domain.clone(),
+                path: self.path.display().to_string(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TraefikSource;
+    use crate::utils::source::{CertificateSource, LocalFiles};
+    use crate::utils::testutil::{TempDir, self_signed_pair};
+    use base64::Engine;
+    use base64::prelude::BASE64_STANDARD;
+    use std::fs;
+
+    #[test]
+    fn test_traefik_source() {
+        let root = TempDir::new("traefik");
+        let acme_json = root.join("acme.json");
+
+        let entry = |(certificate, key): (Vec<u8>, Vec<u8>), main: &str| {
+            serde_json::json!({
+                "domain": { "main": main },
+                "certificate": BASE64_STANDARD.encode(certificate),
+                "key": BASE64_STANDARD.encode(key),
+                "Store": "default"
+            })
+        };
+        let current = self_signed_pair("mail.example.com", 1, 89);
+        fs::write(
+            &acme_json,
+            serde_json::json!({
+                "letsencrypt": {
+                    "Account": {},
+                    "Certificates": [
+                        entry(self_signed_pair("mail.example.com", 80, 10), "mail.example.com"),
+                        entry(current.clone(), "mail.example.com"),
+                        entry(self_signed_pair("www.example.com", 0, 90), "www.example.com"),
+                    ]
+                },
+                "staging": { "Account": {}, "Certificates": null }
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let source = TraefikSource::new(
+            &LocalFiles,
+            acme_json.clone(),
+            "mail.example.com".to_string(),
+            None,
+        );
+        assert_eq!(source.fetch().unwrap().private_key, current.1);
+
+        let source = TraefikSource::new(
+            &LocalFiles,
+            acme_json,
+            "mail.example.com".to_string(),
+            Some("staging".to_string()),
+        );
+        assert!(source.fetch().is_err());
+    }
+}
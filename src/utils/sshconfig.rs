@@ -0,0 +1,505 @@
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+use crate::system::sysinfo::get_loggedin_user;
+use crate::utils::configuration::JobConfiguration;
+use crate::utils::errors::{ConfigurationError, MoorenewError};
+
+const DEFAULT_SSH_PORT: u16 = 22;
+
+/// The parts of an OpenSSH client config moorenew uses: `HostName`, `Port`, `User`,
+/// `IdentityFile` and `ProxyJump` of `Host` blocks. `Match` blocks and `Include` are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SshConfig {
+    blocks: Vec<HostBlock>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HostBlock {
+    /// `None` for a `Match` block, which is never applied.
+    patterns: Option<Vec<String>>,
+    options: Vec<(String, String)>,
+}
+
+/// Options of a host alias, the first value found in the config wins like in OpenSSH.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostOptions {
+    pub hostname: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub identity_files: Vec<String>,
+    /// Comma separated jump hosts, `none` disables jumping.
+    pub proxy_jump: Option<String>,
+}
+
+/// A host to connect to after resolving it through the OpenSSH config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshHost {
+    pub user: String,
+    pub host: String,
+    pub port: u16,
+    /// Key files of `IdentityFile`, tried if the job has no `private_key_path`.
+    pub identity_files: Vec<String>,
+}
+
+/// The SFTP host of a job and the jump hosts the connection is tunneled through, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshRoute {
+    pub jump_hosts: Vec<SshHost>,
+    pub destination: SshHost,
+}
+
+impl SshConfig {
+    /// Reads the config at `path`. A missing file is an empty config.
+    pub fn load(path: &Path) -> Result<SshConfig, MoorenewError> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Ok(SshConfig::parse(&contents)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                debug!(path = %path.display(), "no ssh config");
+                Ok(SshConfig::default())
+            }
+            Err(e) => Err(MoorenewError::ConfigurationError(
+                ConfigurationError::SshConfig {
+                    path: path.display().to_string(),
+                    error: e,
+                },
+            )),
+        }
+    }
+
+    pub fn parse(contents: &str) -> SshConfig {
+        // options before the first `Host` apply to every host
+        let mut blocks = vec![HostBlock {
+            patterns: Some(vec!["*".to_string()]),
+            options: Vec::new(),
+        }];
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, value) = match line.split_once(|c: char| c.is_whitespace() || c == '=') {
+                Some((keyword, value)) => (
+                    keyword.to_lowercase(),
+                    value.trim_start_matches(|c: char| c.is_whitespace() || c == '='),
+                ),
+                None => (line.to_lowercase(), ""),
+            };
+            let value = value.trim();
+
+            match keyword.as_str() {
+                "host" => blocks.push(HostBlock {
+                    patterns: Some(value.split_whitespace().map(unquote).collect()),
+                    options: Vec::new(),
+                }),
+                "match" => blocks.push(HostBlock {
+                    patterns: None,
+                    options: Vec::new(),
+                }),
+                "include" => warn!(value, "Include in the ssh config is not supported"),
+                _ => {
+                    if let Some(block) = blocks.last_mut() {
+                        block.options.push((keyword, unquote(value)));
+                    }
+                }
+            }
+        }
+
+        SshConfig { blocks }
+    }
+
+    /// Collects the options of every block matching `alias`.
+    pub fn host(&self, alias: &str) -> HostOptions {
+        let mut options = HostOptions::default();
+
+        for block in &self.blocks {
+            let Some(patterns) = &block.patterns else {
+                continue;
+            };
+            if !host_matches(patterns, alias) {
+                continue;
+            }
+
+            for (keyword, value) in &block.options {
+                match keyword.as_str() {
+                    "hostname" if options.hostname.is_none() => {
+                        options.hostname = Some(value.replace("%h", alias))
+                    }
+                    "port" if options.port.is_none() => match value.parse() {
+                        Ok(port) => options.port = Some(port),
+                        Err(_) => warn!(value, alias, "ignoring invalid Port in the ssh config"),
+                    },
+                    "user" if options.user.is_none() => options.user = Some(value.clone()),
+                    "identityfile" => options.identity_files.push(value.clone()),
+                    "proxyjump" if options.proxy_jump.is_none() => {
+                        options.proxy_jump = Some(value.clone())
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        options
+    }
+}
+
+/// Resolves `sftp_host` and the jump hosts of `job` through the OpenSSH config. Explicit
+/// `jump_hosts` replace `ProxyJump`, a `sftp_user` and a `sftp_port` other than 22 replace `User`
+/// and `Port`.
+pub fn resolve_route(job: &JobConfiguration) -> Result<SshRoute, MoorenewError> {
+    let config = match job.ssh_config_path()? {
+        Some(path) => SshConfig::load(&path)?,
+        None => SshConfig::default(),
+    };
+    let home = std::env::home_dir();
+
+    let options = config.host(&job.sftp_host);
+    let user = if job.sftp_user.is_empty() {
+        options.user.clone().map_or_else(get_loggedin_user, Ok)?
+    } else {
+        job.sftp_user.clone()
+    };
+    let port = match (job.sftp_port, options.port) {
+        (DEFAULT_SSH_PORT, Some(port)) => port,
+        (port, _) => port,
+    };
+    let host = options
+        .hostname
+        .clone()
+        .unwrap_or_else(|| job.sftp_host.clone());
+    let destination = SshHost {
+        identity_files: expand_identity_files(
+            &options.identity_files,
+            home.as_deref(),
+            &host,
+            &user,
+        ),
+        user,
+        host,
+        port,
+    };
+
+    let jump_specs: Vec<String> = if !job.jump_hosts.is_empty() {
+        job.jump_hosts.clone()
+    } else {
+        match options.proxy_jump.as_deref() {
+            None | Some("none") => Vec::new(),
+            Some(proxy_jump) => proxy_jump.split(',').map(str::to_string).collect(),
+        }
+    };
+    let jump_hosts = jump_specs
+        .iter()
+        .map(|spec| {
+            let (user, alias, port) = parse_jump_host(spec)?;
+            let options = config.host(&alias);
+            let user = user
+                .or(options.user)
+                .unwrap_or_else(|| destination.user.clone());
+            let host = options.hostname.unwrap_or(alias);
+            Ok(SshHost {
+                identity_files: expand_identity_files(
+                    &options.identity_files,
+                    home.as_deref(),
+                    &host,
+                    &user,
+                ),
+                port: port.or(options.port).unwrap_or(DEFAULT_SSH_PORT),
+                user,
+                host,
+            })
+        })
+        .collect::<Result<Vec<SshHost>, MoorenewError>>()?;
+
+    Ok(SshRoute {
+        jump_hosts,
+        destination,
+    })
+}
+
+/// Parses a jump host in the `ProxyJump` format `[user@]host[:port]`, `ssh://` URLs included.
+pub fn parse_jump_host(spec: &str) -> Result<(Option<String>, String, Option<u16>), MoorenewError> {
+    let invalid =
+        || MoorenewError::ConfigurationError(ConfigurationError::InvalidJumpHost(spec.to_string()));
+
+    let trimmed = spec.trim();
+    let trimmed = trimmed.strip_prefix("ssh://").unwrap_or(trimmed);
+    let (user, host) = match trimmed.rsplit_once('@') {
+        Some((user, host)) if !user.is_empty() => (Some(user.to_string()), host),
+        Some(_) => return Err(invalid()),
+        None => (None, trimmed),
+    };
+
+    let (host, port) = if let Some(rest) = host.strip_prefix('[') {
+        // [2001:db8::1]:2222
+        let (host, rest) = rest.split_once(']').ok_or_else(invalid)?;
+        match rest.strip_prefix(':') {
+            Some(port) => (host, Some(port.parse().map_err(|_| invalid())?)),
+            None if rest.is_empty() => (host, None),
+            None => return Err(invalid()),
+        }
+    } else {
+        match host.split_once(':') {
+            Some((host, port)) => (host, Some(port.parse().map_err(|_| invalid())?)),
+            None => (host, None),
+        }
+    };
+
+    if host.is_empty() || host.contains(char::is_whitespace) {
+        return Err(invalid());
+    }
+    Ok((user, host.to_string(), port))
+}
+
+/// Whether `alias` matches one of the patterns of a `Host` line and none of its negated patterns.
+fn host_matches(patterns: &[String], alias: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        if let Some(pattern) = pattern.strip_prefix('!') {
+            if wildcard_match(pattern, alias) {
+                return false;
+            }
+        } else if wildcard_match(pattern, alias) {
+            matched = true;
+        }
+    }
+    matched
+}
+
+/// Matches `*` and `?` wildcards case insensitively.
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let value: Vec<char> = value.to_lowercase().chars().collect();
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    v = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Expands `~`, `%d`, `%h`, `%r` and `%%` in the `IdentityFile` values.
+fn expand_identity_files(
+    identity_files: &[String],
+    home: Option<&Path>,
+    host: &str,
+    user: &str,
+) -> Vec<String> {
+    let home = home
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("~"));
+    identity_files
+        .iter()
+        .map(|file| {
+            let file = match file.strip_prefix("~/") {
+                Some(rest) => format!("{}/{rest}", home.display()),
+                None => file.clone(),
+            };
+            let mut expanded = String::new();
+            let mut chars = file.chars();
+            while let Some(c) = chars.next() {
+                if c != '%' {
+                    expanded.push(c);
+                    continue;
+                }
+                match chars.next() {
+                    Some('d') => expanded.push_str(&home.display().to_string()),
+                    Some('h') => expanded.push_str(host),
+                    Some('r') => expanded.push_str(user),
+                    Some('%') => expanded.push('%'),
+                    Some(other) => {
+                        expanded.push('%');
+                        expanded.push(other);
+                    }
+                    None => expanded.push('%'),
+                }
+            }
+            expanded
+        })
+        .collect()
+}
+
+fn unquote(value: &str) -> String {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        SshConfig, SshHost, expand_identity_files, parse_jump_host, resolve_route, wildcard_match,
+    };
+    use crate::utils::configuration::Configuration;
+    use crate::utils::testutil::TempDir;
+    use std::path::Path;
+
+    const CONFIG: &str = r#"
+# bastion in front of the proxy
+Host bastion
+    HostName bastion.example.com
+    User admin
+    Port 2222
+
+Host npm npm.internal
+    HostName 10.0.0.5
+    ProxyJump bastion
+    IdentityFile ~/.ssh/npm_ed25519
+
+Match exec "true"
+    User nobody
+
+Host *.internal !secret.internal
+    User=mailcow
+    IdentityFile "%d/.ssh/%h"
+
+Host *
+    Port 22
+    User root
+"#;
+
+    #[test]
+    fn test_host_options() {
+        let config = SshConfig::parse(CONFIG);
+
+        let npm = config.host("npm");
+        assert_eq!(npm.hostname.as_deref(), Some("10.0.0.5"));
+        assert_eq!(npm.port, Some(22));
+        assert_eq!(npm.user.as_deref(), Some("root"));
+        assert_eq!(npm.proxy_jump.as_deref(), Some("bastion"));
+        assert_eq!(npm.identity_files, ["~/.ssh/npm_ed25519"]);
+
+        let internal = config.host("npm.internal");
+        assert_eq!(internal.user.as_deref(), Some("mailcow"));
+        assert_eq!(
+            internal.identity_files,
+            ["~/.ssh/npm_ed25519", "%d/.ssh/%h"]
+        );
+
+        let secret = config.host("secret.internal");
+        assert_eq!(secret.user.as_deref(), Some("root"));
+        assert!(secret.identity_files.is_empty());
+
+        let bastion = config.host("BASTION");
+        assert_eq!(bastion.hostname.as_deref(), Some("bastion.example.com"));
+        assert_eq!(bastion.port, Some(2222));
+        assert_eq!(bastion.user.as_deref(), Some("admin"));
+
+        assert_eq!(
+            expand_identity_files(
+                &internal.identity_files,
+                Some(Path::new("/root")),
+                "10.0.0.5",
+                "mailcow"
+            ),
+            ["/root/.ssh/npm_ed25519", "/root/.ssh/10.0.0.5"]
+        );
+    }
+
+    #[test]
+    fn test_resolve_route() {
+        let root = TempDir::new("ssh-config");
+        let config_path = root.join("config");
+        std::fs::write(&config_path, CONFIG).unwrap();
+        let mut job = Configuration::new().jobs.remove(0);
+        job.sftp_host = "npm".to_string();
+        job.sftp_user = String::new();
+        job.ssh_config_path = Some(config_path.display().to_string());
+
+        let route = resolve_route(&job).unwrap();
+        assert_eq!(route.destination.host, "10.0.0.5");
+        assert_eq!(route.destination.user, "root");
+        assert_eq!(route.destination.port, 22);
+        assert_eq!(
+            route.jump_hosts,
+            [SshHost {
+                user: "admin".to_string(),
+                host: "bastion.example.com".to_string(),
+                port: 2222,
+                identity_files: Vec::new(),
+            }]
+        );
+
+        job.sftp_user = "mailcow".to_string();
+        job.sftp_port = 2022;
+        job.jump_hosts = vec!["jump1.example.com".to_string(), "bastion:22".to_string()];
+        let route = resolve_route(&job).unwrap();
+        assert_eq!(route.destination.user, "mailcow");
+        assert_eq!(route.destination.port, 2022);
+        let jump_hosts: Vec<(&str, &str, u16)> = route
+            .jump_hosts
+            .iter()
+            .map(|host| (host.user.as_str(), host.host.as_str(), host.port))
+            .collect();
+        assert_eq!(
+            jump_hosts,
+            [
+                ("root", "jump1.example.com", 22),
+                ("admin", "bastion.example.com", 22)
+            ]
+        );
+
+        job.ssh_config_path = Some("none".to_string());
+        job.jump_hosts = Vec::new();
+        let route = resolve_route(&job).unwrap();
+        assert_eq!(route.destination.host, "npm");
+        assert!(route.jump_hosts.is_empty());
+    }
+
+    #[test]
+    fn test_parse_jump_host() {
+        assert_eq!(
+            parse_jump_host("bastion").unwrap(),
+            (None, "bastion".to_string(), None)
+        );
+        assert_eq!(
+            parse_jump_host("admin@bastion.example.com:2222").unwrap(),
+            (
+                Some("admin".to_string()),
+                "bastion.example.com".to_string(),
+                Some(2222)
+            )
+        );
+        assert_eq!(
+            parse_jump_host("ssh://admin@[2001:db8::1]:2222").unwrap(),
+            (
+                Some("admin".to_string()),
+                "2001:db8::1".to_string(),
+                Some(2222)
+            )
+        );
+        assert!(parse_jump_host("bastion:port").is_err());
+        assert!(parse_jump_host("@bastion").is_err());
+        assert!(parse_jump_host("").is_err());
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*", "anything"));
+        assert!(wildcard_match("*.example.com", "npm.example.com"));
+        assert!(!wildcard_match("*.example.com", "example.com"));
+        assert!(wildcard_match("npm?", "npm1"));
+        assert!(wildcard_match("n*m*", "nginx-mailcow"));
+        assert!(!wildcard_match("npm?", "npm"));
+    }
+}
@@ -0,0 +1,270 @@
+use ssh2::{Channel, Session};
+use std::io::{ErrorKind, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+use crate::utils::errors::MoorenewError;
+use crate::utils::ssh::Keepalive;
+
+/// Bytes buffered in each direction before reading pauses.
+const BUFFER_SIZE: usize = 32 * 1024;
+/// First pause of the copy loop if neither side had anything to do. Every further idle iteration
+/// doubles it up to [`MAX_IDLE_INTERVAL`], any transferred data resets it.
+const MIN_IDLE_INTERVAL: Duration = Duration::from_millis(1);
+const MAX_IDLE_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A direct-tcpip channel through a jump host, forwarding to one end of a socket pair, whose
+/// other end carries the SSH session of the next host. libssh2 sessions need a file descriptor,
+/// so the channel can not be handed to the next session directly.
+pub struct Tunnel {
+    host: String,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// The part of a channel the copy loop uses, so it can be tested without a server.
+trait TunnelChannel: Read + Write {
+    fn send_eof(&mut self) -> std::io::Result<()>;
+    /// Keeps the connection to the jump host alive while the tunnel is idle.
+    fn keepalive(&mut self) -> std::io::Result<()>;
+}
+
+/// A channel with the session of the jump host it belongs to.
+struct SessionChannel {
+    session: Session,
+    channel: Channel,
+}
+
+impl Read for SessionChannel {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        self.channel.read(buffer)
+    }
+}
+
+impl Write for SessionChannel {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        self.channel.write(buffer)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.channel.flush()
+    }
+}
+
+impl TunnelChannel for SessionChannel {
+    fn send_eof(&mut self) -> std::io::Result<()> {
+        self.channel.send_eof().map_err(std::io::Error::from)
+    }
+
+    fn keepalive(&mut self) -> std::io::Result<()> {
+        self.session
+            .keepalive_send()
+            .map(|_| ())
+            .map_err(std::io::Error::from)
+    }
+}
+
+impl Tunnel {
+    /// Opens a channel from the jump host behind `session` to `host`:`port` and returns the socket
+    /// to run the next session on. The session is only used by the tunnel afterwards and sends a
+    /// keepalive every `keepalive_interval`, if set.
+    pub fn open(
+        session: Session,
+        host: &str,
+        port: u16,
+        keepalive_interval: Option<Duration>,
+    ) -> Result<(UnixStream, Tunnel), MoorenewError> {
+        let channel = session
+            .channel_direct_tcpip(host, port, None)
+            .map_err(|e| MoorenewError::SshTunnel {
+                host: host.to_string(),
+                error: e,
+            })?;
+        let (stream, mut forwarded) = UnixStream::pair().map_err(MoorenewError::SSHConnectError)?;
+        forwarded
+            .set_nonblocking(true)
+            .map_err(MoorenewError::SSHConnectError)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread_host = host.to_string();
+        let thread = std::thread::Builder::new()
+            .name(format!("tunnel-{host}"))
+            .spawn(move || {
+                session.set_blocking(false);
+                let mut channel = SessionChannel { session, channel };
+                if let Err(e) = pump(
+                    &mut channel,
+                    &mut forwarded,
+                    keepalive_interval,
+                    &thread_stop,
+                ) {
+                    debug!(error = %e, host = %thread_host, "tunnel closed");
+                }
+            })
+            .map_err(MoorenewError::SSHConnectError)?;
+
+        Ok((
+            stream,
+            Tunnel {
+                host: host.to_string(),
+                stop,
+                thread: Some(thread),
+            },
+        ))
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take()
+            && thread.join().is_err()
+        {
+            warn!(host = %self.host, "tunnel thread panicked");
+        }
+    }
+}
+
+/// Copies between the nonblocking `channel` and `local` until both directions reached EOF or
+/// `stop` is set. EOF is passed on per direction, as the SSH session on `local` may still be
+/// waiting for data after its peer stopped sending. A keepalive is sent every
+/// `keepalive_interval`.
+fn pump<C: TunnelChannel>(
+    channel: &mut C,
+    local: &mut UnixStream,
+    keepalive_interval: Option<Duration>,
+    stop: &AtomicBool,
+) -> std::io::Result<()> {
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut idle_interval = MIN_IDLE_INTERVAL;
+    let mut keepalive = Keepalive::new(keepalive_interval);
+    let mut to_remote = Vec::new();
+    let mut to_local = Vec::new();
+    let (mut local_eof, mut remote_eof) = (false, false);
+    let (mut eof_sent, mut local_closed) = (false, false);
+
+    while !stop.load(Ordering::Relaxed) {
+        let mut idle = true;
+
+        if !local_eof
+            && to_remote.len() < BUFFER_SIZE
+            && let Some(read) = nonblocking(local.read(&mut buffer))?
+        {
+            idle = false;
+            match read {
+                0 => local_eof = true,
+                read => to_remote.extend_from_slice(&buffer[..read]),
+            }
+        }
+        if !to_remote.is_empty() {
+            if let Some(written) = nonblocking(channel.write(&to_remote))? {
+                idle = false;
+                to_remote.drain(..written);
+            }
+        } else if local_eof && !eof_sent && nonblocking(channel.send_eof())?.is_some() {
+            eof_sent = true;
+        }
+
+        if !remote_eof
+            && to_local.len() < BUFFER_SIZE
+            && let Some(read) = nonblocking(channel.read(&mut buffer))?
+        {
+            idle = false;
+            match read {
+                0 => remote_eof = true,
+                read => to_local.extend_from_slice(&buffer[..read]),
+            }
+        }
+        if !to_local.is_empty() {
+            if let Some(written) = nonblocking(local.write(&to_local))? {
+                idle = false;
+                to_local.drain(..written);
+            }
+        } else if remote_eof && !local_closed {
+            local.shutdown(Shutdown::Write)?;
+            local_closed = true;
+        }
+
+        if eof_sent && local_closed {
+            return Ok(());
+        }
+        keepalive.send_if_due(|| Ok(nonblocking(channel.keepalive())?.is_some()))?;
+        if idle {
+            std::thread::sleep(idle_interval);
+            idle_interval = (idle_interval * 2).min(MAX_IDLE_INTERVAL);
+        } else {
+            idle_interval = MIN_IDLE_INTERVAL;
+        }
+    }
+
+    Ok(())
+}
+
+/// Turns the error of an operation which would have blocked into `None`.
+fn nonblocking<T>(result: std::io::Result<T>) -> std::io::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TunnelChannel, pump};
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::AtomicBool;
+
+    impl TunnelChannel for TcpStream {
+        fn send_eof(&mut self) -> std::io::Result<()> {
+            self.shutdown(Shutdown::Write)
+        }
+
+        fn keepalive(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pump() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut channel = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        let (mut session, mut forwarded) = UnixStream::pair().unwrap();
+        channel.set_nonblocking(true).unwrap();
+        forwarded.set_nonblocking(true).unwrap();
+
+        let request = vec![b'a'; 100_000];
+        let pump = std::thread::spawn(move || {
+            pump(&mut channel, &mut forwarded, None, &AtomicBool::new(false)).unwrap()
+        });
+        let writer = {
+            let mut session = session.try_clone().unwrap();
+            let request = request.clone();
+            std::thread::spawn(move || {
+                session.write_all(&request).unwrap();
+                session.shutdown(Shutdown::Write).unwrap();
+            })
+        };
+
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).unwrap();
+        assert_eq!(received, request);
+        server.write_all(b"SSH-2.0-OpenSSH_9.6").unwrap();
+        server.shutdown(Shutdown::Write).unwrap();
+
+        let mut response = String::new();
+        session.read_to_string(&mut response).unwrap();
+        assert_eq!(response, "SSH-2.0-OpenSSH_9.6");
+        writer.join().unwrap();
+        pump.join().unwrap();
+    }
+}
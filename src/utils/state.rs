@@ -0,0 +1,347 @@
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::utils::certvalidation::CertificateInfo;
+use crate::utils::errors::MoorenewError;
+
+/// File in the state directory recording the runs of every job.
+const STATE_FILE: &str = "state.json";
+
+/// What moorenew remembers between runs, shown by `moorenew status`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct State {
+    #[serde(default)]
+    pub jobs: BTreeMap<String, JobState>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct JobState {
+    /// RFC 3339 time of the last run.
+    pub last_run: Option<String>,
+    /// `success`, `partially successful`, `up to date`, `rolled back` or `failed`.
+    pub last_result: Option<String>,
+    /// Error of the last run if it failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// RFC 3339 time of the last run which installed a certificate.
+    pub last_change: Option<String>,
+    /// RFC 3339 time of the last failed run.
+    pub last_failure: Option<String>,
+    #[serde(default)]
+    pub consecutive_failures: u32,
+    /// Certificate installed in `mail_cert_path` after the last run.
+    pub certificate: Option<InstalledCertificate>,
+    /// Fingerprints of the certificates replaced by `moorenew rollback`. Runs do not install them
+    /// again until they are cleared with `moorenew rollback --clear`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rolled_back: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InstalledCertificate {
+    pub fingerprint: String,
+    pub subject: String,
+    pub not_after: String,
+}
+
+impl From<CertificateInfo> for InstalledCertificate {
+    fn from(info: CertificateInfo) -> InstalledCertificate {
+        InstalledCertificate {
+            fingerprint: info.fingerprint,
+            subject: info.subject,
+            not_after: info.not_after,
+        }
+    }
+}
+
+/// Result of a run as recorded by [`State::record_run`].
+pub enum RunResult<'a> {
+    Updated,
+    PartiallyUpdated,
+    UpToDate,
+    /// Holds the fingerprints of the certificates which got replaced.
+    RolledBack(&'a [String]),
+    Failed(&'a MoorenewError),
+}
+
+impl State {
+    pub fn path(state_dir: &Path) -> PathBuf {
+        state_dir.join(STATE_FILE)
+    }
+
+    /// Reads the state from `state_dir`. A missing file is an empty state.
+    pub fn load(state_dir: &Path) -> Result<State, MoorenewError> {
+        let path = State::path(state_dir);
+        let contents = match std::fs::read(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(State::default()),
+            Err(e) => return Err(state_error(&path, e)),
+        };
+
+        serde_json::from_slice(&contents).map_err(|e| state_error(&path, std::io::Error::other(e)))
+    }
+
+    /// Writes the state to a temporary file and renames it into place, so an interrupted write
+    /// never leaves a truncated state behind.
+    pub fn save(&self, state_dir: &Path) -> Result<(), MoorenewError> {
+        let path = State::path(state_dir);
+        let temp_path = path.with_extension("json.tmp");
+        let contents = serde_json::to_vec_pretty(self)
+            .map_err(|e| state_error(&path, std::io::Error::other(e)))?;
+
+        std::fs::create_dir_all(state_dir)
+            .and_then(|_| std::fs::write(&temp_path, contents))
+            .and_then(|_| std::fs::rename(&temp_path, &path))
+            .map_err(|e| state_error(&path, e))
+    }
+
+    /// Records a run of `job` which finished at `time`. `certificate` is the certificate installed
+    /// afterwards, if it could be read.
+    pub fn record_run(
+        &mut self,
+        job: &str,
+        result: RunResult,
+        certificate: Option<InstalledCertificate>,
+        time: chrono::DateTime<Utc>,
+    ) {
+        let time = time.to_rfc3339_opts(SecondsFormat::Secs, true);
+        let state = self.jobs.entry(job.to_string()).or_default();
+
+        state.last_run = Some(time.clone());
+        state.last_error = None;
+        match result {
+            RunResult::Updated | RunResult::PartiallyUpdated => {
+                state.last_change = Some(time);
+                state.consecutive_failures = 0;
+            }
+            RunResult::RolledBack(fingerprints) => {
+                state.last_change = Some(time);
+                state.consecutive_failures = 0;
+                for fingerprint in fingerprints {
+                    if !state.rolled_back.contains(fingerprint) {
+                        state.rolled_back.push(fingerprint.clone());
+                    }
+                }
+            }
+            RunResult::UpToDate => state.consecutive_failures = 0,
+            RunResult::Failed(e) => {
+                state.last_error = Some(e.to_string());
+                state.last_failure = Some(time);
+                state.consecutive_failures += 1;
+            }
+        }
+        state.last_result = Some(
+            match result {
+                RunResult::Updated => "success",
+                RunResult::PartiallyUpdated => "partially successful",
+                RunResult::UpToDate => "up to date",
+                RunResult::RolledBack(_) => "rolled back",
+                RunResult::Failed(_) => "failed",
+            }
+            .to_string(),
+        );
+        if certificate.is_some() {
+            state.certificate = certificate;
+        }
+    }
+
+    /// Forgets the rolled back certificates of `job`, so the next run installs them again if the
+    /// source still serves them. Returns the cleared fingerprints.
+    pub fn clear_rolled_back(&mut self, job: &str) -> Vec<String> {
+        self.jobs
+            .get_mut(job)
+            .map(|state| std::mem::take(&mut state.rolled_back))
+            .unwrap_or_default()
+    }
+}
+
+/// Loads the state, records a run and saves it again. Failures are only logged, as the state must
+/// never fail a run.
+pub fn record_run(
+    state_dir: &Path,
+    job: &str,
+    result: RunResult,
+    certificate: Option<InstalledCertificate>,
+) {
+    let recorded = State::load(state_dir).and_then(|mut state| {
+        state.record_run(job, result, certificate, Utc::now());
+        state.save(state_dir)
+    });
+    if let Err(e) = recorded {
+        warn!(error = %e, "could not record the run in the state file");
+    }
+}
+
+/// Returns the fingerprints of the certificates rolled back for `job`. Failures are only logged,
+/// the certificates are not skipped then.
+pub fn rolled_back(state_dir: &Path, job: &str) -> Vec<String> {
+    match State::load(state_dir) {
+        Ok(mut state) => state
+            .jobs
+            .remove(job)
+            .map(|state| state.rolled_back)
+            .unwrap_or_default(),
+        Err(e) => {
+            warn!(error = %e, "could not read the rolled back certificates from the state file");
+            Vec::new()
+        }
+    }
+}
+
+/// A job and its state as printed by `moorenew status`.
+#[derive(Serialize, Debug, Clone)]
+pub struct JobStatus {
+    pub name: String,
+    #[serde(flatten)]
+    pub state: JobState,
+}
+
+/// Formats `statuses` as a table with one line per job.
+pub fn format_status_table(statuses: &[JobStatus]) -> String {
+    let header = [
+        "JOB",
+        "LAST RUN",
+        "RESULT",
+        "FAILURES",
+        "LAST CHANGE",
+        "NOT AFTER",
+        "FINGERPRINT",
+    ];
+    let mut rows: Vec<[String; 7]> = vec![header.map(str::to_string)];
+    for status in statuses {
+        let state = &status.state;
+        let certificate = state.certificate.as_ref();
+        let or_dash = |value: Option<&String>| value.cloned().unwrap_or_else(|| "-".to_string());
+        rows.push([
+            status.name.clone(),
+            state
+                .last_run
+                .clone()
+                .unwrap_or_else(|| "never".to_string()),
+            or_dash(state.last_result.as_ref()),
+            state.consecutive_failures.to_string(),
+            or_dash(state.last_change.as_ref()),
+            or_dash(certificate.map(|certificate| &certificate.not_after)),
+            certificate
+                .map(|certificate| certificate.fingerprint.chars().take(16).collect())
+                .unwrap_or_else(|| "-".to_string()),
+        ]);
+    }
+
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(value, width)| format!("{value:<width$}"))
+                .collect::<Vec<String>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn state_error(path: &Path, error: std::io::Error) -> MoorenewError {
+    MoorenewError::StateFile {
+        path: path.display().to_string(),
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InstalledCertificate, JobStatus, RunResult, State, format_status_table};
+    use crate::utils::errors::MoorenewError;
+    use crate::utils::testutil::TempDir;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_record_run() {
+        let state_dir = TempDir::new("state");
+        let certificate = InstalledCertificate {
+            fingerprint: "ab".repeat(32),
+            subject: "CN=mail.example.com".to_string(),
+            not_after: "Jan  1 00:00:00 2030 GMT".to_string(),
+        };
+
+        let mut state = State::load(&state_dir).unwrap();
+        state.record_run(
+            "example.com",
+            RunResult::Updated,
+            Some(certificate.clone()),
+            Utc.with_ymd_and_hms(2026, 10, 1, 12, 0, 0).unwrap(),
+        );
+        let error = MoorenewError::NoBackupAvailable;
+        for day in 2..4 {
+            state.record_run(
+                "example.com",
+                RunResult::Failed(&error),
+                None,
+                Utc.with_ymd_and_hms(2026, 10, day, 12, 0, 0).unwrap(),
+            );
+        }
+        state.save(&state_dir).unwrap();
+
+        let job = &State::load(&state_dir).unwrap().jobs["example.com"];
+        assert_eq!(job.last_run.as_deref(), Some("2026-10-03T12:00:00Z"));
+        assert_eq!(job.last_result.as_deref(), Some("failed"));
+        assert_eq!(job.last_change.as_deref(), Some("2026-10-01T12:00:00Z"));
+        assert_eq!(job.consecutive_failures, 2);
+        assert_eq!(job.certificate.as_ref(), Some(&certificate));
+
+        let mut state = State::load(&state_dir).unwrap();
+        let restored = InstalledCertificate {
+            fingerprint: "cd".repeat(32),
+            ..certificate.clone()
+        };
+        for _ in 0..2 {
+            state.record_run(
+                "example.com",
+                RunResult::RolledBack(std::slice::from_ref(&certificate.fingerprint)),
+                Some(restored.clone()),
+                Utc.with_ymd_and_hms(2026, 10, 4, 12, 0, 0).unwrap(),
+            );
+        }
+        let rolled_back = &state.jobs["example.com"];
+        assert_eq!(rolled_back.last_result.as_deref(), Some("rolled back"));
+        assert_eq!(
+            rolled_back.last_change.as_deref(),
+            Some("2026-10-04T12:00:00Z")
+        );
+        assert_eq!(rolled_back.consecutive_failures, 0);
+        assert_eq!(rolled_back.certificate.as_ref(), Some(&restored));
+        assert_eq!(
+            rolled_back.rolled_back,
+            vec![certificate.fingerprint.clone()]
+        );
+        assert_eq!(
+            state.clear_rolled_back("example.com"),
+            vec![certificate.fingerprint.clone()]
+        );
+        assert!(state.jobs["example.com"].rolled_back.is_empty());
+
+        let table = format_status_table(&[
+            JobStatus {
+                name: "example.com".to_string(),
+                state: job.clone(),
+            },
+            JobStatus {
+                name: "example.org".to_string(),
+                state: Default::default(),
+            },
+        ]);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("JOB          LAST RUN"));
+        assert!(lines[1].contains("failed  2"));
+        assert!(lines[1].ends_with("abababababababab"));
+        assert!(lines[2].starts_with("example.org  never"));
+    }
+}
@@ -0,0 +1,108 @@
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::{X509, X509NameBuilder};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A directory below the system temporary directory, named after the test and the process so
+/// parallel tests and test runs do not collide. It is created empty and removed with everything
+/// in it when dropped, even if the test fails.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("moorenew-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A new P-256 key.
+pub fn key() -> PKey<Private> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+}
+
+/// Certificate for `dns_name` issued `age_days` ago and valid for another `valid_days`, signed by
+/// `issuer` or self-signed.
+pub fn certificate(
+    dns_name: &str,
+    key: &PKey<Private>,
+    issuer: Option<(&X509, &PKey<Private>)>,
+    age_days: i64,
+    valid_days: i64,
+) -> X509 {
+    let now = chrono::Utc::now().timestamp();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", dns_name).unwrap();
+    let name = name.build();
+
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    builder
+        .set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+        .unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_pubkey(key).unwrap();
+    builder
+        .set_not_before(&Asn1Time::from_unix(now - age_days * 86_400).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::from_unix(now + valid_days * 86_400).unwrap())
+        .unwrap();
+    let san = SubjectAlternativeName::new()
+        .dns(dns_name)
+        .build(&builder.x509v3_context(issuer.map(|(certificate, _)| certificate.as_ref()), None))
+        .unwrap();
+    builder.append_extension(san).unwrap();
+    match issuer {
+        Some((issuer, issuer_key)) => {
+            builder.set_issuer_name(issuer.subject_name()).unwrap();
+            builder.sign(issuer_key, MessageDigest::sha256()).unwrap();
+        }
+        None => {
+            builder.set_issuer_name(&name).unwrap();
+            builder.sign(key, MessageDigest::sha256()).unwrap();
+        }
+    }
+    builder.build()
+}
+
+/// Self-signed certificate for `dns_name` like [`certificate`], PEM encoded with its private key.
+pub fn self_signed_pair(dns_name: &str, age_days: i64, valid_days: i64) -> (Vec<u8>, Vec<u8>) {
+    let key = key();
+    (
+        certificate(dns_name, &key, None, age_days, valid_days)
+            .to_pem()
+            .unwrap(),
+        key.private_key_to_pem_pkcs8().unwrap(),
+    )
+}
@@ -0,0 +1,316 @@
+use openssl::hash::MessageDigest;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, instrument, warn};
+
+use crate::utils::errors::MoorenewError;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Protocol spoken on a port before the TLS handshake starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StartTls {
+    None,
+    Smtp,
+    Imap,
+    Pop3,
+}
+
+impl StartTls {
+    fn for_port(port: u16) -> StartTls {
+        match port {
+            25 | 587 => StartTls::Smtp,
+            143 => StartTls::Imap,
+            110 => StartTls::Pop3,
+            _ => StartTls::None,
+        }
+    }
+}
+
+/// Result of checking the certificate served on a single port.
+#[derive(Debug, Clone)]
+pub struct EndpointResult {
+    pub port: u16,
+    /// Fingerprint of the served leaf certificate, `None` if it could not be retrieved.
+    pub fingerprint: Option<String>,
+    pub error: Option<String>,
+}
+
+impl EndpointResult {
+    pub fn matches(&self, expected_fingerprint: &str) -> bool {
+        self.fingerprint.as_deref() == Some(expected_fingerprint)
+    }
+}
+
+/// verify_served_certificates connects to every port of `host` and compares the SHA-256
+/// fingerprint of the presented leaf certificate with `expected_fingerprint`. Ports 25 and 587
+/// use SMTP STARTTLS, 143 IMAP STARTTLS and 110 POP3 STLS, all other ports implicit TLS.
+/// Mismatching ports are checked again until `retry_window` is over, because services may take
+/// a moment to pick up the new certificate.
+#[instrument(skip(expected_fingerprint))]
+pub fn verify_served_certificates(
+    host: &str,
+    ports: &[u16],
+    expected_fingerprint: &str,
+    retry_window: Duration,
+) -> Result<Vec<EndpointResult>, MoorenewError> {
+    let started = Instant::now();
+    let mut results: Vec<EndpointResult> = Vec::new();
+
+    loop {
+        for &port in ports {
+            if results
+                .iter()
+                .any(|result| result.port == port && result.matches(expected_fingerprint))
+            {
+                continue;
+            }
+
+            let result = match fetch_served_fingerprint(host, port) {
+                Ok(fingerprint) => EndpointResult {
+                    port,
+                    fingerprint: Some(fingerprint),
+                    error: None,
+                },
+                Err(e) => EndpointResult {
+                    port,
+                    fingerprint: None,
+                    error: Some(e.to_string()),
+                },
+            };
+            debug!(port, fingerprint = ?result.fingerprint, error = ?result.error, "checked served certificate");
+
+            results.retain(|existing| existing.port != port);
+            results.push(result);
+        }
+
+        let mismatched: Vec<u16> = results
+            .iter()
+            .filter(|result| !result.matches(expected_fingerprint))
+            .map(|result| result.port)
+            .collect();
+
+        if mismatched.is_empty() {
+            info!(ports = ?ports, "all endpoints serve the installed certificate");
+            results.sort_by_key(|result| result.port);
+            return Ok(results);
+        }
+
+        if started.elapsed() + RETRY_INTERVAL > retry_window {
+            for result in results
+                .iter()
+                .filter(|result| !result.matches(expected_fingerprint))
+            {
+                warn!(port = result.port, fingerprint = ?result.fingerprint, error = ?result.error, "endpoint does not serve the installed certificate");
+            }
+            return Err(MoorenewError::TlsVerificationFailed { ports: mismatched });
+        }
+
+        debug!(ports = ?mismatched, "endpoints do not serve the installed certificate yet, retrying");
+        sleep(RETRY_INTERVAL);
+    }
+}
+
+/// Returns the lowercase hex encoded SHA-256 fingerprint of the leaf certificate served on
+/// `host:port`. The certificate chain is not verified, only its fingerprint is of interest.
+pub fn fetch_served_fingerprint(host: &str, port: u16) -> std::io::Result<String> {
+    let address = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| std::io::Error::other(format!("could not resolve {host}")))?;
+    let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+
+    start_tls(&stream, StartTls::for_port(port))?;
+
+    let mut connector =
+        SslConnector::builder(SslMethod::tls_client()).map_err(std::io::Error::other)?;
+    connector.set_verify(SslVerifyMode::NONE);
+    let tls = connector
+        .build()
+        .configure()
+        .map_err(std::io::Error::other)?
+        .verify_hostname(false)
+        .connect(host, stream)
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    let certificate = tls
+        .ssl()
+        .peer_certificate()
+        .ok_or_else(|| std::io::Error::other("no certificate presented"))?;
+    let fingerprint = certificate
+        .digest(MessageDigest::sha256())
+        .map_err(std::io::Error::other)?;
+
+    Ok(fingerprint.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn start_tls(stream: &TcpStream, protocol: StartTls) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut writer = stream;
+
+    match protocol {
+        StartTls::None => Ok(()),
+        StartTls::Smtp => {
+            expect_smtp_reply(&mut reader, "220")?;
+            writer.write_all(b"EHLO moorenew\r\n")?;
+            expect_smtp_reply(&mut reader, "250")?;
+            writer.write_all(b"STARTTLS\r\n")?;
+            expect_smtp_reply(&mut reader, "220")
+        }
+        StartTls::Imap => {
+            expect_line(&mut reader, "* OK")?;
+            writer.write_all(b"a1 STARTTLS\r\n")?;
+            loop {
+                let line = read_line(&mut reader)?;
+                if line.starts_with("a1 OK") {
+                    return Ok(());
+                }
+                if line.starts_with("a1 ") {
+                    return Err(unexpected_reply(&line));
+                }
+            }
+        }
+        StartTls::Pop3 => {
+            expect_line(&mut reader, "+OK")?;
+            writer.write_all(b"STLS\r\n")?;
+            expect_line(&mut reader, "+OK")
+        }
+    }
+}
+
+/// Reads a possibly multi-line SMTP reply, e.g. `250-mail.example.com` followed by `250 SMTPUTF8`.
+fn expect_smtp_reply<R: BufRead>(reader: &mut R, code: &str) -> std::io::Result<()> {
+    loop {
+        let line = read_line(reader)?;
+        if !line.starts_with(code) {
+            return Err(unexpected_reply(&line));
+        }
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+fn expect_line<R: BufRead>(reader: &mut R, prefix: &str) -> std::io::Result<()> {
+    let line = read_line(reader)?;
+    if line.starts_with(prefix) {
+        Ok(())
+    } else {
+        Err(unexpected_reply(&line))
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> std::io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "connection closed before starttls",
+        ));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+fn unexpected_reply(line: &str) -> std::io::Error {
+    std::io::Error::other(format!("unexpected reply `{line}` while starting tls"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verify_served_certificates;
+    use crate::utils::errors::MoorenewError;
+    use crate::utils::testutil::{certificate, key};
+    use openssl::hash::MessageDigest;
+    use openssl::ssl::{SslAcceptor, SslMethod};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    /// Starts a TLS server with a fresh self-signed certificate, which speaks SMTP before the
+    /// handshake if `smtp` is set. Returns the port and the certificate fingerprint.
+    fn tls_server(smtp: bool) -> (u16, String) {
+        let key = key();
+        let certificate = certificate("localhost", &key, None, 0, 1);
+        let fingerprint = certificate
+            .digest(MessageDigest::sha256())
+            .unwrap()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_private_key(&key).unwrap();
+        acceptor.set_certificate(&certificate).unwrap();
+        let acceptor = acceptor.build();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                if smtp {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    stream.write_all(b"220 localhost ESMTP\r\n").unwrap();
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    stream
+                        .write_all(b"250-localhost\r\n250 STARTTLS\r\n")
+                        .unwrap();
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    stream.write_all(b"220 Ready to start TLS\r\n").unwrap();
+                }
+                let _ = acceptor.accept(stream);
+            }
+        });
+
+        (port, fingerprint)
+    }
+
+    #[test]
+    fn test_verify_served_certificates() {
+        let (port, fingerprint) = tls_server(false);
+
+        let results =
+            verify_served_certificates("127.0.0.1", &[port], &fingerprint, Duration::ZERO).unwrap();
+        assert!(results[0].matches(&fingerprint));
+
+        assert!(matches!(
+            verify_served_certificates("127.0.0.1", &[port], "00", Duration::ZERO),
+            Err(MoorenewError::TlsVerificationFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_smtp_starttls() {
+        let (port, fingerprint) = tls_server(true);
+        let mut stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        super::start_tls(&stream, super::StartTls::Smtp).unwrap();
+
+        let connector = openssl::ssl::SslConnector::builder(openssl::ssl::SslMethod::tls_client())
+            .unwrap()
+            .build();
+        let mut configuration = connector.configure().unwrap();
+        configuration.set_verify(openssl::ssl::SslVerifyMode::NONE);
+        let tls = configuration
+            .verify_hostname(false)
+            .connect("localhost", &mut stream)
+            .unwrap();
+        let served: String = tls
+            .ssl()
+            .peer_certificate()
+            .unwrap()
+            .digest(MessageDigest::sha256())
+            .unwrap()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(served, fingerprint);
+    }
+}